@@ -0,0 +1,195 @@
+//! Throughput of the line-based conversion against a plain `io::copy` of the decompressed
+//! entries, which is the most any conversion strategy could reach on the same archive, and its
+//! dependence on `--buffer-size`, `--mmap` and the decompression thread of `Options::pipeline`.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use datasets::{DEFAULT_BUFFER_SIZE, Options, stream_tar_gz, stream_zip};
+use flate2::{Compression, read::MultiGzDecoder, write::GzEncoder};
+use tempfile::TempDir;
+use zip::{ZipArchive, write::FileOptions};
+
+const FILES: usize = 4;
+const LINES_PER_FILE: usize = 50_000;
+
+/// Lines per entry of the zip with many small entries, where `--mmap` saves the most seeks.
+const LINES_PER_SMALL_FILE: usize = 50;
+
+/// `--buffer-size` values compared: the default, 64 KiB and 1 MiB.
+const BUFFER_SIZES: &[usize] = &[DEFAULT_BUFFER_SIZE, 64 * 1024, 1024 * 1024];
+
+/// Log-like lines that compress about as well as the real datasets.
+fn file_contents(file: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    for i in 0..LINES_PER_FILE {
+        writeln!(
+            data,
+            "081109 2035{:02} {} INFO dfs.DataNode$PacketResponder: Received block blk_{} of size {} from /10.250.{}.{}",
+            i % 60,
+            i % 997,
+            (i * 7919 + file) % 1_000_003,
+            i * 31 % 67_108_864,
+            i % 256,
+            file
+        )
+        .unwrap();
+    }
+    data
+}
+
+/// Write the same files into `Bench.tar.gz` and `Bench.zip`; returns their paths and the
+/// uncompressed size of all files.
+fn fixtures(dir: &TempDir) -> (PathBuf, PathBuf, u64) {
+    let tar_path = dir.path().join("Bench.tar.gz");
+    let zip_path = dir.path().join("Bench.zip");
+    let encoder = GzEncoder::new(File::create(&tar_path).unwrap(), Compression::default());
+    let mut tar = tar::Builder::new(encoder);
+    let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+    let mut size = 0;
+    for file in 0..FILES {
+        let name = format!("logs/{file}.log");
+        let data = file_contents(file);
+        size += data.len() as u64;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, &name, &data[..]).unwrap();
+        zip.start_file(name, FileOptions::default()).unwrap();
+        zip.write_all(&data).unwrap();
+    }
+    tar.into_inner().unwrap().finish().unwrap();
+    zip.finish().unwrap();
+    (tar_path, zip_path, size)
+}
+
+/// Write the lines of one fixture file into `Many.zip` as entries of
+/// [`LINES_PER_SMALL_FILE`] lines each; returns its path and the uncompressed size.
+fn many_entries_zip(dir: &TempDir) -> (PathBuf, u64) {
+    let path = dir.path().join("Many.zip");
+    let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+    let data = file_contents(0);
+    let lines: Vec<&[u8]> = data.split_inclusive(|&b| b == b'\n').collect();
+    for (i, chunk) in lines.chunks(LINES_PER_SMALL_FILE).enumerate() {
+        zip.start_file(format!("logs/{i}.log"), FileOptions::default())
+            .unwrap();
+        for line in chunk {
+            zip.write_all(line).unwrap();
+        }
+    }
+    zip.finish().unwrap();
+    (path, data.len() as u64)
+}
+
+fn copy_tar_gz(path: &Path) {
+    let mut archive = tar::Archive::new(MultiGzDecoder::new(File::open(path).unwrap()));
+    for entry in archive.entries().unwrap() {
+        io::copy(&mut entry.unwrap(), &mut io::sink()).unwrap();
+    }
+}
+
+fn copy_zip(path: &Path) {
+    let mut archive = ZipArchive::new(File::open(path).unwrap()).unwrap();
+    for i in 0..archive.len() {
+        io::copy(&mut archive.by_index(i).unwrap(), &mut io::sink()).unwrap();
+    }
+}
+
+fn streaming(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let (tar_path, zip_path, size) = fixtures(&dir);
+    let options = Options::default();
+
+    let mut group = c.benchmark_group("tar.gz");
+    group.throughput(Throughput::Bytes(size));
+    group.sample_size(10);
+    group.bench_function("lines", |b| {
+        b.iter(|| stream_tar_gz(&tar_path, &mut io::sink(), &options).unwrap())
+    });
+    group.bench_function("io_copy", |b| b.iter(|| copy_tar_gz(&tar_path)));
+    group.finish();
+
+    let mut group = c.benchmark_group("zip");
+    group.throughput(Throughput::Bytes(size));
+    group.sample_size(10);
+    group.bench_function("lines", |b| {
+        b.iter(|| stream_zip(&zip_path, &mut io::sink(), &options).unwrap())
+    });
+    group.bench_function("io_copy", |b| b.iter(|| copy_zip(&zip_path)));
+    group.finish();
+}
+
+fn buffer_size(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let (tar_path, _, size) = fixtures(&dir);
+
+    let mut group = c.benchmark_group("buffer_size");
+    group.throughput(Throughput::Bytes(size));
+    group.sample_size(10);
+    for &buffer_size in BUFFER_SIZES {
+        let options = Options {
+            buffer_size: Some(buffer_size),
+            ..Options::default()
+        };
+        group.bench_with_input(
+            BenchmarkId::from_parameter(buffer_size),
+            &options,
+            |b, options| b.iter(|| stream_tar_gz(&tar_path, &mut io::sink(), options).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn mmap(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, zip_path, size) = fixtures(&dir);
+    let (many_path, many_size) = many_entries_zip(&dir);
+
+    for (name, path, size) in [
+        ("mmap", &zip_path, size),
+        ("mmap_many", &many_path, many_size),
+    ] {
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Bytes(size));
+        group.sample_size(10);
+        for mmap in [false, true] {
+            let options = Options {
+                mmap,
+                ..Options::default()
+            };
+            let id = if mmap { "mapped" } else { "read" };
+            group.bench_with_input(BenchmarkId::from_parameter(id), &options, |b, options| {
+                b.iter(|| stream_zip(path, &mut io::sink(), options).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+fn pipeline(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let (tar_path, _, size) = fixtures(&dir);
+
+    let mut group = c.benchmark_group("pipeline");
+    group.throughput(Throughput::Bytes(size));
+    group.sample_size(10);
+    for pipeline in [false, true] {
+        let options = Options {
+            pipeline,
+            ..Options::default()
+        };
+        let id = if pipeline { "pipelined" } else { "sequential" };
+        group.bench_with_input(BenchmarkId::from_parameter(id), &options, |b, options| {
+            b.iter(|| stream_tar_gz(&tar_path, &mut io::sink(), options).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, streaming, buffer_size, mmap, pipeline);
+criterion_main!(benches);
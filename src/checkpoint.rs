@@ -0,0 +1,40 @@
+//! Records of how far the output of an archive got, so an interrupted conversion can be resumed.
+
+use std::{fs, io::ErrorKind, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// The part of an archive's output that was written completely: the first `files` inner files
+/// (counting those that pass the entry filters), made up of `lines` lines and `bytes` bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub files: usize,
+    pub lines: u64,
+    pub bytes: u64,
+}
+
+impl Checkpoint {
+    /// Read the checkpoint saved at `path`, `None` if there is none.
+    pub fn load(path: &Path) -> Result<Option<Checkpoint>> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+        };
+        let checkpoint = serde_json::from_slice(&contents)
+            .with_context(|| format!("parsing {}", path.display()))?;
+        Ok(Some(checkpoint))
+    }
+
+    /// Save the checkpoint to `path`, through a temporary file so that a crash leaves either the
+    /// previous checkpoint or this one.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)
+            .with_context(|| format!("writing {}", Path::new(&tmp).display()))?;
+        fs::rename(&tmp, path).with_context(|| format!("writing {}", path.display()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+//! A builder front end to [`Options`] and [`convert_archive_with`].
+
+use std::{io::Write, path::Path};
+
+use anyhow::Result;
+use encoding_rs::Encoding;
+use globset::GlobSet;
+
+use crate::{
+    Dedup, Format, InvalidUtf8, LineNumbers, LogLines, Options, Stats, convert_archive_with,
+    list::{ArchiveEntry, list_archive},
+};
+
+/// Converts archives with a fixed set of [`Options`], built with [`Converter::builder`].
+///
+/// ```no_run
+/// use datasets::{Converter, Format};
+///
+/// let converter = Converter::builder().dedup(true).format(Format::Ndjson).build();
+/// let mut out = Vec::new();
+/// converter.convert("Spark.tar.gz".as_ref(), &mut out)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Converter {
+    options: Options,
+}
+
+impl Converter {
+    /// Start from the default options, which reproduce the plain output.
+    pub fn builder() -> ConverterBuilder {
+        ConverterBuilder::default()
+    }
+
+    /// The options archives are converted with.
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Append the contents of the archive at `input` to `writer`.
+    pub fn convert(&self, input: &Path, writer: &mut impl Write) -> Result<Stats> {
+        convert_archive_with(input, writer, &self.options)
+    }
+
+    /// Iterate over the lines of the archive at `input`, see [`LogLines`].
+    pub fn lines(&self, input: &Path) -> Result<LogLines> {
+        LogLines::open_with(input, self.options.clone())
+    }
+
+    /// List the inner files of the archive at `input` that a conversion would read.
+    pub fn list(&self, input: &Path) -> Result<Vec<ArchiveEntry>> {
+        list_archive(input, &self.options)
+    }
+}
+
+/// Chainable setters for the most common [`Options`]; [`ConverterBuilder::options`] gives
+/// access to the rest.
+#[derive(Clone, Debug, Default)]
+pub struct ConverterBuilder {
+    options: Options,
+}
+
+impl ConverterBuilder {
+    /// Replace all options at once, e.g. to continue from a prepared set.
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// See [`Options::annotate`].
+    pub fn annotate(mut self, annotate: bool) -> Self {
+        self.options.annotate = annotate;
+        self
+    }
+
+    /// See [`Options::line_numbers`].
+    pub fn line_numbers(mut self, line_numbers: LineNumbers) -> Self {
+        self.options.line_numbers = Some(line_numbers);
+        self
+    }
+
+    /// See [`Options::format`].
+    pub fn format(mut self, format: Format) -> Self {
+        self.options.format = format;
+        self
+    }
+
+    /// See [`Options::encoding`].
+    pub fn encoding(mut self, encoding: &'static Encoding) -> Self {
+        self.options.encoding = Some(encoding);
+        self
+    }
+
+    /// See [`Options::invalid_utf8`].
+    pub fn invalid_utf8(mut self, invalid_utf8: InvalidUtf8) -> Self {
+        self.options.invalid_utf8 = invalid_utf8;
+        self
+    }
+
+    /// See [`Options::include`].
+    pub fn include(mut self, include: GlobSet) -> Self {
+        self.options.include = include;
+        self
+    }
+
+    /// See [`Options::exclude`].
+    pub fn exclude(mut self, exclude: GlobSet) -> Self {
+        self.options.exclude = exclude;
+        self
+    }
+
+    /// Collapse runs of identical lines ([`Dedup::Consecutive`]), or keep every line.
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.options.dedup = if dedup {
+            Dedup::Consecutive
+        } else {
+            Dedup::Off
+        };
+        self
+    }
+
+    /// Drop every line seen before in the archive ([`Dedup::Global`]).
+    pub fn dedup_global(mut self) -> Self {
+        self.options.dedup = Dedup::Global;
+        self
+    }
+
+    /// See [`Options::trim_trailing`].
+    pub fn trim_trailing(mut self, trim_trailing: bool) -> Self {
+        self.options.trim_trailing = trim_trailing;
+        self
+    }
+
+    /// See [`Options::drop_empty`].
+    pub fn drop_empty(mut self, drop_empty: bool) -> Self {
+        self.options.drop_empty = drop_empty;
+        self
+    }
+
+    /// See [`Options::head`].
+    pub fn head(mut self, lines: u64) -> Self {
+        self.options.head = Some(lines);
+        self
+    }
+
+    /// See [`Options::max_ratio`].
+    pub fn max_ratio(mut self, max_ratio: u64) -> Self {
+        self.options.max_ratio = Some(max_ratio);
+        self
+    }
+
+    /// Finish building.
+    pub fn build(self) -> Converter {
+        Converter {
+            options: self.options,
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! The set of lines seen so far for `--dedup-global`, moved to disk when it doesn't fit in
+//! memory.
+
+use std::{collections::HashSet, mem};
+
+use anyhow::Result;
+use rusqlite::Connection;
+
+/// Remembers distinct lines, in memory up to a budget and in a temporary SQLite table beyond it.
+pub(crate) struct SeenLines {
+    /// Bytes of lines after which `lines` is moved to `disk`. `None` never spills.
+    max_memory: Option<usize>,
+    lines: HashSet<String>,
+    /// Approximate heap usage of `lines`.
+    memory: usize,
+    /// Holds every line once spilled; `lines` stays empty from then on.
+    disk: Option<Connection>,
+}
+
+const INSERT: &str = "INSERT OR IGNORE INTO seen (line) VALUES (?1)";
+
+impl SeenLines {
+    pub(crate) fn new(max_memory: Option<usize>) -> Self {
+        Self {
+            max_memory,
+            lines: HashSet::new(),
+            memory: 0,
+            disk: None,
+        }
+    }
+
+    /// Remember `line`; `false` if it was seen before.
+    pub(crate) fn insert(&mut self, line: &str) -> Result<bool> {
+        if let Some(disk) = &self.disk {
+            return Ok(disk.prepare_cached(INSERT)?.execute([line])? == 1);
+        }
+        if self.lines.contains(line) {
+            return Ok(false);
+        }
+        self.memory += line.len() + mem::size_of::<String>();
+        self.lines.insert(line.to_string());
+        if self.max_memory.is_some_and(|max| self.memory > max) {
+            self.spill()?;
+        }
+        Ok(true)
+    }
+
+    /// Move the lines held in memory into a table of a temporary database, which SQLite deletes
+    /// once it is closed. Nothing needs to survive a crash, so there is no journal and a single
+    /// transaction that is never committed.
+    fn spill(&mut self) -> Result<()> {
+        log::debug!(
+            "--dedup-global holds {} distinct lines, moving them to disk",
+            self.lines.len()
+        );
+        let disk = Connection::open("")?;
+        let cache_kib = self.max_memory.unwrap_or(0) / 1024;
+        disk.execute_batch(&format!(
+            "PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF; \
+             PRAGMA cache_size = -{cache_kib}; \
+             CREATE TABLE seen (line TEXT PRIMARY KEY) WITHOUT ROWID; \
+             BEGIN"
+        ))?;
+        {
+            let mut insert = disk.prepare_cached(INSERT)?;
+            for line in self.lines.drain() {
+                insert.execute([line])?;
+            }
+        }
+        self.lines = HashSet::new();
+        self.memory = 0;
+        self.disk = Some(disk);
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+//! Protection against archives that decompress to vastly more than their own size.
+
+use std::{
+    cell::Cell,
+    io::{self, Read},
+    rc::Rc,
+};
+
+/// Expansions are only judged once this many bytes were produced, so small, highly repetitive
+/// logs don't trip the check.
+const MIN_CHECKED_BYTES: u64 = 1 << 20;
+
+/// Counts the bytes read through it into a shared counter.
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R> CountingReader<R> {
+    pub(crate) fn new(inner: R, count: Rc<Cell<u64>>) -> Self {
+        Self { inner, count }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Fails with an [`io::ErrorKind::Other`] error once the decompressed `inner` has produced more
+/// than `max_ratio` times the bytes counted in `source`.
+pub(crate) struct RatioGuard<R> {
+    inner: R,
+    source: Rc<Cell<u64>>,
+    produced: u64,
+    max_ratio: u64,
+}
+
+impl<'a, R: Read + 'a> RatioGuard<R> {
+    /// Guard `inner` when `max_ratio` is set, otherwise pass it through unchanged.
+    pub(crate) fn wrap(
+        inner: R,
+        source: Rc<Cell<u64>>,
+        max_ratio: Option<u64>,
+    ) -> Box<dyn Read + 'a> {
+        match max_ratio {
+            Some(max_ratio) => Box::new(RatioGuard {
+                inner,
+                source,
+                produced: 0,
+                max_ratio,
+            }),
+            None => Box::new(inner),
+        }
+    }
+}
+
+impl<R: Read> Read for RatioGuard<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.produced += n as u64;
+        let source = self.source.get().max(1);
+        if self.produced > MIN_CHECKED_BYTES && self.produced / source > self.max_ratio {
+            return Err(io::Error::other(format!(
+                "decompressed {} bytes from {}, more than the maximum ratio of {}",
+                self.produced, source, self.max_ratio
+            )));
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,148 @@
+//! Opening archive files for reading.
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use anyhow::{Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+
+use crate::Options;
+
+/// An archive file on disk. Reports how far into the (compressed) file we are to a progress bar
+/// when [`Options::progress`] is set, and hashes what is read when [`Options::hash_input`] is.
+pub(crate) struct Input {
+    file: File,
+    len: u64,
+    bar: Option<ProgressBar>,
+    hasher: Option<Sha256>,
+    /// Set once anything seeks; the bytes hashed so far are then not the file in order.
+    seeked: bool,
+    /// [`Options::bytes_read`], and how far into the file it was advanced.
+    read: Option<(Arc<AtomicU64>, u64)>,
+    pos: u64,
+}
+
+impl Input {
+    pub(crate) fn open(path: &Path, options: &Options) -> Result<Input> {
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        let len = file.metadata()?.len();
+        let bar = match &options.progress {
+            Some(multi) => {
+                let bar = multi.add(ProgressBar::new(len));
+                bar.set_style(
+                    ProgressStyle::with_template(
+                        "{msg:24!} [{bar:40}] {bytes}/{total_bytes} ({eta})",
+                    )
+                    .expect("valid progress template")
+                    .progress_chars("=> "),
+                );
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                bar.set_message(name.into_owned());
+                Some(bar)
+            }
+            None => None,
+        };
+        Ok(Input {
+            file,
+            len,
+            bar,
+            hasher: options.hash_input.then(Sha256::new),
+            seeked: false,
+            read: options.bytes_read.clone().map(|read| (read, 0)),
+            pos: 0,
+        })
+    }
+
+    /// Map the whole file into memory for seeking without a syscall each time. `None` when the
+    /// file can't be mapped (e.g. a pipe or other special file).
+    ///
+    /// Reads through the map are neither shown as progress nor hashed as they happen;
+    /// [`Input::finish`] hashes the file from the start instead.
+    pub(crate) fn map(&mut self) -> Option<Mmap> {
+        // SAFETY: the archive is only read; truncating it while mapped is the same hazard as
+        // any other tool reading a file that is being rewritten, and we accept it.
+        match unsafe { Mmap::map(&self.file) } {
+            Ok(map) => {
+                self.seeked = true;
+                Some(map)
+            }
+            Err(e) => {
+                log::debug!("not memory-mapping the archive: {e}");
+                None
+            }
+        }
+    }
+
+    /// Move to `pos`, advancing [`Options::bytes_read`] by what lies past the furthest position
+    /// so far. Zips seek back and forth, but each byte of the file is counted once.
+    fn advance(&mut self, pos: u64) {
+        self.pos = pos;
+        if let Some((read, counted)) = &mut self.read
+            && pos > *counted
+        {
+            read.fetch_add(pos - *counted, Ordering::Relaxed);
+            *counted = pos;
+        }
+    }
+
+    /// SHA-256 of the whole file, if [`Options::hash_input`] is set.
+    ///
+    /// Streaming formats were hashed as they were read, so only the unread tail is hashed here.
+    /// Zip seeks around the file, so it is hashed again from the start instead.
+    pub(crate) fn finish(mut self) -> Result<Option<[u8; 32]>> {
+        let Some(mut hasher) = self.hasher.take() else {
+            return Ok(None);
+        };
+        if self.seeked {
+            hasher = Sha256::new();
+            self.file.rewind()?;
+        }
+        io::copy(&mut self.file, &mut hasher)?;
+        Ok(Some(hasher.finalize().into()))
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read(buf)?;
+        if let Some(bar) = &self.bar {
+            bar.inc(n as u64);
+        }
+        self.advance(self.pos + n as u64);
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl Seek for Input {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = self.file.seek(pos)?;
+        self.seeked = true;
+        self.advance(pos);
+        if let Some(bar) = &self.bar {
+            bar.set_position(pos);
+        }
+        Ok(pos)
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+        // Done with the archive, whether or not all of it was read (e.g. through a map).
+        self.advance(self.len);
+    }
+}
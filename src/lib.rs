@@ -0,0 +1,1595 @@
+//! Streaming conversion of Loghub archives into line-separated text.
+//!
+//! Every regular file inside an archive is appended to a single [`Write`] sink, one log entry
+//! per line and always `\n`-terminated (CRLF is normalized to LF). Lines that are not valid
+//! UTF-8 are skipped, unless a source [`Options::encoding`] is given to transcode from.
+//!
+//! ```no_run
+//! let mut out = Vec::new();
+//! datasets::convert_archive("Spark.tar.gz".as_ref(), &mut out)?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! [`Converter::builder`] sets up [`Options`] step by step for repeated conversions, and
+//! [`LogLines`] iterates over the lines of an archive instead of writing them.
+
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+use anyhow::{Context, Result, bail};
+use bzip2::read::BzDecoder;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use flate2::read::MultiGzDecoder;
+use globset::GlobSet;
+use indicatif::MultiProgress;
+use regex::{Regex, RegexSet};
+use sevenz_rust::{Password, SevenZArchiveEntry, SevenZReader};
+use tar::{Archive, EntryType};
+use xz2::read::XzDecoder;
+use zip::read::{ZipArchive, ZipFile};
+
+mod checkpoint;
+mod converter;
+mod dedup;
+mod guard;
+mod input;
+mod lines;
+mod list;
+mod memory;
+mod output;
+mod parse;
+mod pipeline;
+mod sort;
+mod sqlite;
+mod stats;
+mod time;
+
+pub use checkpoint::Checkpoint;
+pub use converter::{Converter, ConverterBuilder};
+use guard::{CountingReader, RatioGuard};
+use input::Input;
+pub use lines::{LogLine, LogLines};
+pub use list::{ArchiveEntry, list_archive};
+pub use memory::{MemoryGate, MemoryPermit, estimate_memory};
+use output::OutputWriter;
+pub use output::{Format, LineSink};
+pub use sort::Sort;
+pub use sqlite::SqliteSink;
+pub use stats::Stats;
+
+/// Knobs controlling how lines are emitted. [`Options::default`] reproduces the plain output.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Prefix every line with `dataset\tinner/path\tmtime\t` (text format only), the
+    /// modification time of the inner file in ISO 8601 or empty when the archive has none.
+    pub annotate: bool,
+    /// Split lines of known datasets (e.g. `HDFS`, `Spark`, `BGL`) into timestamp, level,
+    /// component and content: tab-separated columns in text output, separate fields in NDJSON.
+    /// Lines not matching their dataset's format keep only the content.
+    pub parse: bool,
+    /// Number the emitted lines: a leading `N\t` in text output, an `"n"` field in NDJSON and
+    /// an `n` column in CSV.
+    pub line_numbers: Option<LineNumbers>,
+    /// Shape of the emitted records.
+    pub format: Format,
+    /// Start the output of every archive with a row of column names, for [`Format::Csv`].
+    pub csv_header: bool,
+    /// When non-empty, only inner files whose path matches one of these globs are processed.
+    pub include: GlobSet,
+    /// Inner files whose path matches one of these globs are skipped.
+    pub exclude: GlobSet,
+    /// Character set the logs are stored in. `None` means UTF-8: invalid lines are handled
+    /// according to `invalid_utf8` rather than transcoded.
+    pub encoding: Option<&'static Encoding>,
+    /// What happens to lines that are not valid UTF-8.
+    pub invalid_utf8: InvalidUtf8,
+    /// Show a bar per archive, tracking how much of the archive file has been read.
+    pub progress: Option<MultiProgress>,
+    /// Advanced by the bytes of archive files read, counting each file up to its size once it
+    /// is done, for a progress bar over all archives of a run.
+    pub bytes_read: Option<Arc<AtomicU64>>,
+    /// Which repeated lines of an archive are dropped.
+    pub dedup: Dedup,
+    /// Lines longer than this many bytes are handled according to `on_long_line`.
+    pub max_line_bytes: Option<usize>,
+    /// What happens to lines over `max_line_bytes`.
+    pub on_long_line: LongLine,
+    /// Remove ANSI escape sequences (CSI, e.g. the SGR ones setting colors) from every line,
+    /// before any filter sees it.
+    pub strip_ansi: bool,
+    /// Rewrite lines holding a JSON object or array without the whitespace between tokens, so
+    /// records differing only in formatting compare equal (e.g. for `dedup`). Other lines are
+    /// left alone.
+    pub flatten_json_logs: bool,
+    /// Lines not matching this pattern continue the record started by an earlier line that did
+    /// (e.g. the frames of a Java stack trace after its timestamped first line), and are appended
+    /// to it with `continuation_joiner` before any filter sees the record. Records don't span
+    /// inner files.
+    pub join_continuations: Option<Regex>,
+    /// Put between the lines of a joined record. `None` means [`DEFAULT_CONTINUATION_JOINER`].
+    pub continuation_joiner: Option<String>,
+    /// Strip trailing whitespace from every line.
+    pub trim_trailing: bool,
+    /// Leave out lines that are empty (after `trim_trailing`), including file separators.
+    pub drop_empty: bool,
+    /// Replace every tab in a line by this, so tab-separated output such as `annotate` or
+    /// `parse` keeps its columns. Tabs the converter writes itself are left alone.
+    pub replace_tabs: Option<String>,
+    /// When non-empty, only lines matching one of these patterns are written.
+    pub grep: RegexSet,
+    /// Lines matching one of these patterns are left out.
+    pub grep_invert: RegexSet,
+    /// Keep each line (that passed the other filters) with this probability, from 0 to 1.
+    pub sample: Option<f64>,
+    /// Seed of the random choices of `sample`, so a sample can be reproduced. Every archive
+    /// (or inner file with [`convert_archive_split`]) starts from it afresh.
+    pub sample_seed: u64,
+    /// Stop each archive after this many written lines.
+    pub head: Option<u64>,
+    /// Keep only the last this many lines of each archive. File separators are left out.
+    pub tail: Option<usize>,
+    /// Emit the records of each archive sorted instead of in archive order. File separators are
+    /// left out.
+    pub sort: Sort,
+    /// Bytes of records `sort` may buffer before spilling sorted runs to temporary files, and
+    /// of lines [`Dedup::Global`] may remember before moving them to a temporary database. Each
+    /// of them gets the whole budget. `None` keeps everything in memory.
+    pub max_memory: Option<usize>,
+    /// Line written before the contents of every inner file, with `{name}` replaced by the
+    /// entry path (text format only). Replaces the empty line otherwise written after each file.
+    pub file_separator: Option<String>,
+    /// Leave out the empty line written after each inner file. Every emitted line already ends
+    /// in `\n`, so files stay apart without it.
+    pub no_separator_newline: bool,
+    /// End every record, file separators included, with a NUL byte instead of `\n`, for tools
+    /// such as `xargs -0`. Lines themselves still end at `\n` in the input.
+    pub null_separated: bool,
+    /// Fail an archive once its contents expand to more than this many times the compressed
+    /// bytes read (protection against zip bombs). Zip entries declaring such a ratio in their
+    /// header are rejected up front.
+    pub max_ratio: Option<u64>,
+    /// Drop lines whose leading timestamp is before this instant. Timestamps are parsed
+    /// according to the dataset name (e.g. `HDFS`, `Spark`); other datasets are not filtered.
+    pub since: Option<NaiveDateTime>,
+    /// Drop lines whose leading timestamp is at or after this instant.
+    pub until: Option<NaiveDateTime>,
+    /// With `since` / `until` or `normalize_timestamps`, also drop lines without a parseable
+    /// timestamp instead of keeping them.
+    pub strict_time: bool,
+    /// Rewrite the leading timestamp of every line as ISO 8601 (`2015-10-18T18:01:47.978`), read
+    /// in the dataset's format as for `since`. Lines without one are written unchanged.
+    pub normalize_timestamps: bool,
+    /// Decompress tar archives read from a path on a separate thread, overlapping inflation with
+    /// line processing and writing.
+    pub pipeline: bool,
+    /// Compute [`Stats::input_sha256`] for archives read from a path.
+    pub hash_input: bool,
+    /// Capacity of the buffer decompressed data is split into lines from. `None` means
+    /// [`DEFAULT_BUFFER_SIZE`].
+    pub buffer_size: Option<usize>,
+    /// Read zip archives through a memory map instead of file reads and seeks, falling back to
+    /// the file when it can't be mapped.
+    pub mmap: bool,
+    /// Skip inner tar and zip files smaller than this many bytes, as recorded in their header
+    /// (the compressed size for gzipped files inside a tar).
+    pub min_file_size: Option<u64>,
+    /// Skip inner tar and zip files larger than this many bytes.
+    pub max_file_size: Option<u64>,
+    /// Fail the archive on a zip entry whose CRC doesn't match, checking it before any of its
+    /// lines are written (which decompresses every entry twice). Otherwise the mismatch is
+    /// logged and counted in [`Stats::crc_mismatches`] once the entry was written.
+    pub strict_crc: bool,
+    /// Once set (e.g. by a Ctrl-C handler), archives end early as if `head` had been reached:
+    /// what was read so far is still written out completely.
+    pub interrupt: Option<Arc<AtomicBool>>,
+    /// Bytes of output the archives sharing it may write together. Once a record would take
+    /// more than is left, archives end early like with `interrupt`.
+    pub byte_budget: Option<Arc<ByteBudget>>,
+    /// After every inner file written completely, flush the writer and save a [`Checkpoint`]
+    /// of the output so far to this path.
+    pub checkpoint: Option<PathBuf>,
+    /// Continue an output that an interrupted conversion with the same options left at this
+    /// [`Checkpoint`]: its inner files are skipped, and line numbers and checkpoints go on from
+    /// it. The writer is expected to hold exactly `bytes` bytes of that output.
+    pub resume_from: Checkpoint,
+}
+
+/// Output bytes shared by every archive of a run, see [`Options::byte_budget`].
+#[derive(Debug)]
+pub struct ByteBudget {
+    limit: u64,
+    used: AtomicU64,
+    exhausted: AtomicBool,
+}
+
+impl ByteBudget {
+    /// A budget of `limit` bytes, none of them used yet.
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: AtomicU64::new(0),
+            exhausted: AtomicBool::new(false),
+        }
+    }
+
+    /// Bytes taken so far.
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Whether a record was refused for not fitting in what was left.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::Relaxed)
+    }
+
+    /// Take `bytes` from the budget, or nothing and `false` if fewer are left, which exhausts it
+    /// for good.
+    pub(crate) fn spend(&self, bytes: u64) -> bool {
+        if self.is_exhausted() {
+            return false;
+        }
+        let taken = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used + bytes).filter(|&total| total <= self.limit)
+            })
+            .is_ok();
+        if !taken {
+            self.exhausted.store(true, Ordering::Relaxed);
+        }
+        taken
+    }
+}
+
+/// What [`Options::join_continuations`] joins lines with by default: an escaped `\n`, so the
+/// record stays on one output line.
+pub const DEFAULT_CONTINUATION_JOINER: &str = "\\n";
+
+/// Capacity of read and write buffers unless configured otherwise, that of [`BufReader::new`].
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Treatment of lines longer than [`Options::max_line_bytes`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LongLine {
+    /// Cut the line at the limit and append `…`.
+    #[default]
+    Truncate,
+    /// Leave the line out entirely.
+    Drop,
+}
+
+/// What [`Options::line_numbers`] counts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LineNumbers {
+    /// Count through the whole archive.
+    Global,
+    /// Start again from 1 with every inner file.
+    PerFile,
+}
+
+/// Treatment of lines that are not valid UTF-8.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum InvalidUtf8 {
+    /// Leave the line out.
+    #[default]
+    Skip,
+    /// Keep the line, with each invalid sequence replaced by `U+FFFD`.
+    Lossy,
+    /// Write [`INVALID_UTF8_PLACEHOLDER`] in place of the line.
+    Replace,
+}
+
+/// Line written for an invalid one with [`InvalidUtf8::Replace`].
+pub const INVALID_UTF8_PLACEHOLDER: &str = "<invalid UTF-8>";
+
+/// Suppression of repeated log lines within one archive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dedup {
+    /// Keep every line.
+    #[default]
+    Off,
+    /// Collapse runs of identical lines into one, like `uniq`.
+    Consecutive,
+    /// Drop every line seen before. Keeps all distinct lines of the archive, on disk once they
+    /// exceed [`Options::max_memory`].
+    Global,
+}
+
+impl Options {
+    /// Whether the inner file `name` passes the `include` / `exclude` filters.
+    fn wants_entry(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.is_match(name)) && !self.exclude.is_match(name)
+    }
+
+    /// Byte ending every emitted record: `\n`, or NUL with [`Options::null_separated`].
+    pub(crate) fn terminator(&self) -> u8 {
+        if self.null_separated { b'\0' } else { b'\n' }
+    }
+
+    /// Whether [`Options::interrupt`] has been set.
+    pub fn interrupted(&self) -> bool {
+        self.interrupt
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// Whether [`Options::byte_budget`] is used up.
+    pub fn out_of_budget(&self) -> bool {
+        self.byte_budget
+            .as_ref()
+            .is_some_and(|budget| budget.is_exhausted())
+    }
+
+    /// Whether archives end early, being [interrupted](Options::interrupted) or
+    /// [out of budget](Options::out_of_budget).
+    pub fn stopped(&self) -> bool {
+        self.interrupted() || self.out_of_budget()
+    }
+
+    /// Whether an inner file of `size` bytes is within `min_file_size` / `max_file_size`.
+    fn wants_size(&self, size: u64) -> bool {
+        self.min_file_size.is_none_or(|min| size >= min)
+            && self.max_file_size.is_none_or(|max| size <= max)
+    }
+}
+
+/// Copy `reader` (the inner file `name`) to the sink line by line, first transcoding it from
+/// `options.encoding` to UTF-8 when one is set.
+fn copy_lines<R: Read, W: Write>(
+    reader: R,
+    name: &str,
+    options: &Options,
+    out: &mut OutputWriter<W>,
+) -> Result<()> {
+    let capacity = options.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+    match options.encoding {
+        Some(encoding) => {
+            let decoder = DecodeReaderBytesBuilder::new()
+                .encoding(Some(encoding))
+                .build(reader);
+            copy_utf8_lines(
+                BufReader::with_capacity(capacity, decoder),
+                name,
+                options,
+                out,
+            )
+        }
+        None => copy_utf8_lines(
+            BufReader::with_capacity(capacity, reader),
+            name,
+            options,
+            out,
+        ),
+    }
+}
+
+/// Copy `reader` to the sink line by line, handling lines that are not valid UTF-8 according
+/// to `options.invalid_utf8`. Every line written is terminated by a single `\n`; a trailing
+/// `\r\n` is stripped like a `\n`, so CRLF endings are normalized. With
+/// [`Options::join_continuations`], the lines of a record are written as one.
+fn copy_utf8_lines<R: BufRead, W: Write>(
+    mut reader: R,
+    name: &str,
+    options: &Options,
+    out: &mut OutputWriter<W>,
+) -> Result<()> {
+    let mut raw = Vec::new();
+    let mut number = 0;
+    let joiner = options
+        .continuation_joiner
+        .as_deref()
+        .unwrap_or(DEFAULT_CONTINUATION_JOINER);
+    // The record being joined and the number of its first line.
+    let mut pending: Option<(u64, String)> = None;
+    while !out.is_done() {
+        raw.clear();
+        number += 1;
+        if reader
+            .read_until(b'\n', &mut raw)
+            .with_context(|| format!("reading {name}"))?
+            == 0
+        {
+            break;
+        }
+        let line = raw.strip_suffix(b"\n").unwrap_or(&raw);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => Cow::Borrowed(line),
+            Err(e) => match options.invalid_utf8 {
+                InvalidUtf8::Skip => {
+                    log::debug!("Skipping invalid UTF-8 line {} of {} ({})", number, name, e);
+                    out.stats.skipped_non_utf8 += 1;
+                    continue;
+                }
+                InvalidUtf8::Lossy => {
+                    log::debug!(
+                        "Replacing invalid UTF-8 in line {} of {} ({})",
+                        number,
+                        name,
+                        e
+                    );
+                    out.stats.replaced_non_utf8 += 1;
+                    String::from_utf8_lossy(line)
+                }
+                InvalidUtf8::Replace => {
+                    log::debug!(
+                        "Replacing invalid UTF-8 line {} of {} ({})",
+                        number,
+                        name,
+                        e
+                    );
+                    out.stats.replaced_non_utf8 += 1;
+                    Cow::Borrowed(INVALID_UTF8_PLACEHOLDER)
+                }
+            },
+        };
+        let Some(starts) = &options.join_continuations else {
+            out.write_line(name, number, &line)?;
+            continue;
+        };
+        match &mut pending {
+            Some((_, record)) if !starts.is_match(&line) => {
+                record.push_str(joiner);
+                record.push_str(&line);
+            }
+            _ => {
+                if let Some((start, record)) = pending.replace((number, line.into_owned())) {
+                    out.write_line(name, start, &record)?;
+                }
+            }
+        }
+    }
+    if let Some((start, record)) = pending {
+        out.write_line(name, start, &record)?;
+    }
+    out.stats.files += 1;
+    Ok(())
+}
+
+/// Call `visit` with the path, modification time and contents of every regular file of the (already
+/// decompressed) tar stream `reader` that passes the entry filters, until it returns `false`.
+/// Individually gzipped entries are decompressed. Returns the entries that are not regular files
+/// in [`Stats::skipped_entries`].
+fn visit_tar<R: Read>(
+    reader: R,
+    options: &Options,
+    mut visit: impl FnMut(&str, Option<NaiveDateTime>, &mut dyn Read) -> Result<bool>,
+) -> Result<Stats> {
+    let mut counts = Stats::default();
+    let mut archive = Archive::new(reader);
+    // Concatenated archives (`cat a.tar.gz b.tar.gz`) carry an end-of-archive marker per member.
+    archive.set_ignore_zeros(true);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        match entry.header().entry_type() {
+            EntryType::Regular => {}
+            // GNU long-name and pax records only carry metadata.
+            EntryType::Directory
+            | EntryType::GNULongName
+            | EntryType::GNULongLink
+            | EntryType::XHeader
+            | EntryType::XGlobalHeader => continue,
+            // Links point at content that is (or isn't) in the archive already; following them
+            // would emit the same log twice.
+            kind => {
+                log::debug!("skipping {:?} entry {}", kind, name);
+                counts.skipped_entries += 1;
+                continue;
+            }
+        }
+        if !options.wants_entry(&name) || !options.wants_size(entry.size()) {
+            continue;
+        }
+        let mtime = entry
+            .header()
+            .mtime()
+            .ok()
+            .and_then(|secs| unix_time(secs as i64));
+        // Some datasets gzip every log file individually before tarring them up.
+        let more = if strip_suffix_ignore_case(&name, ".gz").is_some() {
+            let size = Rc::new(Cell::new(entry.size()));
+            let decoder = MultiGzDecoder::new(entry);
+            visit(
+                &name,
+                mtime,
+                &mut RatioGuard::wrap(decoder, size, options.max_ratio),
+            )?
+        } else {
+            visit(&name, mtime, &mut entry)?
+        };
+        if !more {
+            break;
+        }
+    }
+    Ok(counts)
+}
+
+/// Like [`visit_tar`] for a zip archive, visiting entries in lexicographic order of their names.
+/// The returned counts also include the entries that failed their CRC check (see
+/// [`Options::strict_crc`]).
+fn visit_zip<R: Read + Seek>(
+    reader: R,
+    options: &Options,
+    mut visit: impl FnMut(&str, Option<NaiveDateTime>, &mut dyn Read) -> Result<bool>,
+) -> Result<Stats> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut counts = Stats::default();
+
+    // Visit entries sorted by name so the output doesn't depend on the order the zip tool
+    // happened to store them in.
+    let mut names = Vec::with_capacity(archive.len());
+    for index in 0..archive.len() {
+        names.push((zip_entry_name(&archive.by_index_raw(index)?), index));
+    }
+    names.sort();
+
+    for (name, index) in &names {
+        let zf = archive.by_index(*index)?;
+        match zip_entry_type(&zf) {
+            ZipEntryType::File => {}
+            ZipEntryType::Directory => continue,
+            ZipEntryType::Special => {
+                let mode = zf.unix_mode().unwrap_or(0);
+                log::debug!("skipping {name}, which is not a regular file (mode {mode:o})");
+                counts.skipped_entries += 1;
+                continue;
+            }
+        }
+        if !options.wants_entry(name) || !options.wants_size(zf.size()) {
+            continue;
+        }
+        let compressed = zf.compressed_size();
+        if let Some(max_ratio) = options.max_ratio
+            && zf.size() / compressed.max(1) > max_ratio
+        {
+            bail!(
+                "{} claims to expand {} bytes to {}, more than the maximum ratio of {}",
+                name,
+                compressed,
+                zf.size(),
+                max_ratio
+            );
+        }
+        let mtime = zip_time(zf.last_modified());
+        let zf = if options.strict_crc {
+            let mut zf = zf;
+            if let Err(e) = io::copy(&mut zf, &mut io::sink()) {
+                if is_crc_mismatch(&e) {
+                    bail!("{name} fails its CRC check, the archive is corrupt");
+                }
+                return Err(e).with_context(|| format!("reading {name}"));
+            }
+            drop(zf);
+            archive.by_index(*index)?
+        } else {
+            zf
+        };
+        let mut entry = CrcCheck {
+            inner: RatioGuard::wrap(zf, Rc::new(Cell::new(compressed)), options.max_ratio),
+            mismatch: false,
+        };
+        let more = visit(name, mtime, &mut entry)?;
+        if more {
+            // The CRC is only checked once the entry has been read to the end.
+            io::copy(&mut entry, &mut io::sink()).with_context(|| format!("reading {name}"))?;
+        }
+        if entry.mismatch {
+            log::error!("{name} fails its CRC check; its lines may be corrupt");
+            counts.crc_mismatches += 1;
+        }
+        if !more {
+            break;
+        }
+    }
+    Ok(counts)
+}
+
+/// A modification time in seconds since the Unix epoch, as UTC.
+fn unix_time(secs: i64) -> Option<NaiveDateTime> {
+    DateTime::from_timestamp(secs, 0).map(|time| time.naive_utc())
+}
+
+/// A zip modification time, which is local to wherever the archive was made.
+fn zip_time(time: zip::DateTime) -> Option<NaiveDateTime> {
+    NaiveDate::from_ymd_opt(time.year().into(), time.month().into(), time.day().into())?
+        .and_hms_opt(
+            time.hour().into(),
+            time.minute().into(),
+            time.second().into(),
+        )
+}
+
+/// Whether `e` is the error a zip entry reader fails with at its end when the CRC doesn't match.
+/// The zip crate raises it as a plain [`io::ErrorKind::Other`] with no error type of its own to
+/// match, so it goes by the message, which a unit test pins.
+fn is_crc_mismatch(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::Other && e.to_string() == "Invalid checksum"
+}
+
+/// Ends a zip entry at a CRC mismatch instead of failing the read, remembering it.
+struct CrcCheck<R> {
+    inner: R,
+    mismatch: bool,
+}
+
+impl<R: Read> Read for CrcCheck<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            Err(e) if is_crc_mismatch(&e) => {
+                self.mismatch = true;
+                Ok(0)
+            }
+            result => result,
+        }
+    }
+}
+
+/// Like [`visit_zip`] for a 7z archive, visiting entries in archive order: the files of a solid
+/// block are compressed as one stream, so any other order would decode it more than once.
+fn visit_7z<R: Read + Seek>(
+    mut reader: R,
+    options: &Options,
+    mut visit: impl FnMut(&str, Option<NaiveDateTime>, &mut dyn Read) -> Result<bool>,
+) -> Result<Stats> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.rewind()?;
+    let mut archive = SevenZReader::new(reader, len, Password::empty())?;
+    if let Some(max_ratio) = options.max_ratio {
+        let size: u64 = archive.archive().files.iter().map(|f| f.size()).sum();
+        if size / len.max(1) > max_ratio {
+            bail!(
+                "archive claims to expand {} bytes to {}, more than the maximum ratio of {}",
+                len,
+                size,
+                max_ratio
+            );
+        }
+    }
+    // `visit` fails with an anyhow error, which has to be passed around the 7z error type.
+    let mut failed = None;
+    let mut done = false;
+    let mut counts = Stats::default();
+    let result = archive.for_each_entries(|entry, data| {
+        if done {
+            return Ok(false);
+        }
+        let name = entry.name();
+        let mode = sevenz_unix_mode(entry);
+        if mode.is_some_and(is_special_mode) {
+            log::debug!(
+                "skipping {name}, which is not a regular file (mode {:o})",
+                mode.unwrap_or(0)
+            );
+            counts.skipped_entries += 1;
+        } else if !entry.is_directory()
+            && options.wants_entry(name)
+            && options.wants_size(entry.size())
+        {
+            let mtime = entry
+                .has_last_modified_date
+                .then(|| unix_time(entry.last_modified_date().to_unix_time()))
+                .flatten();
+            match visit(name, mtime, data) {
+                Ok(more) => done = !more,
+                Err(e) => {
+                    failed = Some(e);
+                    return Err(sevenz_rust::Error::other("visiting entry failed"));
+                }
+            }
+        }
+        if !done {
+            // The next file of a solid block starts where this one ends, so skipped or partly
+            // read files are still decoded to the end.
+            io::copy(data, &mut io::sink())?;
+        }
+        Ok(!done)
+    });
+    match failed {
+        Some(e) => Err(e),
+        None => {
+            result?;
+            Ok(counts)
+        }
+    }
+}
+
+/// Unix mode of a 7z entry, which p7zip keeps in the high half of the Windows attributes.
+pub(crate) fn sevenz_unix_mode(entry: &SevenZArchiveEntry) -> Option<u32> {
+    const FILE_ATTRIBUTE_UNIX_EXTENSION: u32 = 0x8000;
+    let attributes = entry.windows_attributes();
+    (entry.has_windows_attributes && attributes & FILE_ATTRIBUTE_UNIX_EXTENSION != 0)
+        .then_some(attributes >> 16)
+}
+
+/// Mask and values of the file type bits of a Unix mode.
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+const S_IFDIR: u32 = 0o040000;
+
+/// Whether Unix `mode` is that of something other than a regular file or directory.
+pub(crate) fn is_special_mode(mode: u32) -> bool {
+    !matches!(mode & S_IFMT, 0 | S_IFREG | S_IFDIR)
+}
+
+/// What a zip entry holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ZipEntryType {
+    File,
+    Directory,
+    /// A symlink (stored with its target as contents), device, FIFO or socket.
+    Special,
+}
+
+/// Type of a zip entry, going by its name and, for zips made on Unix, its mode bits.
+pub(crate) fn zip_entry_type(zf: &ZipFile) -> ZipEntryType {
+    match zf.unix_mode() {
+        _ if zf.is_dir() => ZipEntryType::Directory,
+        Some(mode) if is_special_mode(mode) => ZipEntryType::Special,
+        Some(mode) if mode & S_IFMT == S_IFDIR => ZipEntryType::Directory,
+        _ => ZipEntryType::File,
+    }
+}
+
+/// Path of a zip entry. Names without the UTF-8 flag are meant to be CP437, but many tools write
+/// UTF-8 without setting it, so raw bytes that are valid UTF-8 are taken as such and only the
+/// rest is decoded as CP437.
+pub(crate) fn zip_entry_name(zf: &ZipFile) -> String {
+    match std::str::from_utf8(zf.name_raw()) {
+        Ok(name) => name.to_string(),
+        Err(_) => zf.name().to_string(),
+    }
+}
+
+/// Append every regular file of the (already decompressed) tar stream `reader` to `writer`.
+/// `dataset` is the name used for `--annotate` prefixes.
+///
+/// Entries are emitted in archive order: the stream can't be rewound, so unlike zip they are
+/// not sorted by name.
+pub fn stream_tar<R: Read, W: Write>(
+    reader: R,
+    dataset: &str,
+    writer: &mut W,
+    options: &Options,
+) -> Result<Stats> {
+    let mut out = OutputWriter::new(writer, options, dataset);
+    let counts = visit_tar(reader, options, |name, mtime, entry| {
+        write_tar_entry(name, mtime, entry, options, &mut out)
+    })?;
+    out.stats += counts;
+    out.finish()
+}
+
+/// Write the tar entry `name` to `out`; unlike zip entries, tar entries follow each other
+/// without an empty line. Returns whether to go on with the next entry.
+fn write_tar_entry<W: Write>(
+    name: &str,
+    mtime: Option<NaiveDateTime>,
+    entry: &mut dyn Read,
+    options: &Options,
+    out: &mut OutputWriter<W>,
+) -> Result<bool> {
+    if out.skips_file() {
+        return Ok(true);
+    }
+    out.set_mtime(mtime);
+    out.start_file(name)?;
+    copy_lines(entry, name, options, out)?;
+    out.file_done()?;
+    Ok(!out.is_done())
+}
+
+/// Write the zip entry `name` to `out`, followed by the separator between files. Returns
+/// whether to go on with the next entry.
+fn write_zip_entry<W: Write>(
+    name: &str,
+    mtime: Option<NaiveDateTime>,
+    entry: &mut dyn Read,
+    options: &Options,
+    out: &mut OutputWriter<W>,
+) -> Result<bool> {
+    if out.skips_file() {
+        return Ok(true);
+    }
+    out.set_mtime(mtime);
+    out.start_file(name)?;
+    copy_lines(entry, name, options, out)?;
+    out.end_file(name)?;
+    out.file_done()?;
+    Ok(!out.is_done())
+}
+
+/// Decompress the stream of a tar or single-file `kind` read from `reader`, failing once it
+/// expands more than [`Options::max_ratio`] allows.
+pub(crate) fn decoder<'a>(
+    kind: ArchiveKind,
+    reader: impl Read + 'a,
+    options: &Options,
+) -> Box<dyn Read + 'a> {
+    let consumed = Rc::new(Cell::new(0));
+    let reader = CountingReader::new(reader, consumed.clone());
+    let max_ratio = options.max_ratio;
+    match kind.compression() {
+        ArchiveKind::Gz => RatioGuard::wrap(MultiGzDecoder::new(reader), consumed, max_ratio),
+        ArchiveKind::Bz2 => RatioGuard::wrap(BzDecoder::new(reader), consumed, max_ratio),
+        ArchiveKind::Xz => RatioGuard::wrap(XzDecoder::new(reader), consumed, max_ratio),
+        ArchiveKind::Zst => match zstd::Decoder::new(reader) {
+            Ok(decoder) => RatioGuard::wrap(decoder, consumed, max_ratio),
+            // Only fails to allocate its context; surface that on the first read.
+            Err(e) => Box::new(FailingReader(Some(e))),
+        },
+        kind => unreachable!("{kind:?} is not a compressed stream"),
+    }
+}
+
+/// Append every regular file of the tar archive of the given `kind` at `path` to `writer`.
+fn stream_tar_file<W: Write>(
+    path: &Path,
+    kind: ArchiveKind,
+    writer: &mut W,
+    options: &Options,
+) -> Result<Stats> {
+    let mut file = Input::open(path, options)?;
+    let dataset = dataset_stem(path);
+    let mut stats = if options.pipeline {
+        pipeline::pipelined(
+            || decoder(kind, &mut file, options),
+            |reader| stream_tar(reader, &dataset, writer, options),
+        )?
+    } else {
+        stream_tar(decoder(kind, &mut file, options), &dataset, writer, options)?
+    };
+    stats.input_sha256 = file.finish()?;
+    Ok(stats)
+}
+
+/// Append every regular file contained in a `.tar.gz` archive to `writer`.
+/// Adds a single `\n` after each file so logs remain one-per-line.
+///
+/// All members of a multi-member gzip file are decompressed, not just the first.
+pub fn stream_tar_gz<W: Write>(path: &Path, writer: &mut W, options: &Options) -> Result<Stats> {
+    stream_tar_file(path, ArchiveKind::TarGz, writer, options)
+}
+
+/// Append every regular file contained in a `.tar.bz2` archive to `writer`.
+pub fn stream_tar_bz2<W: Write>(path: &Path, writer: &mut W, options: &Options) -> Result<Stats> {
+    stream_tar_file(path, ArchiveKind::TarBz2, writer, options)
+}
+
+/// Append every regular file contained in a `.tar.xz` archive to `writer`.
+pub fn stream_tar_xz<W: Write>(path: &Path, writer: &mut W, options: &Options) -> Result<Stats> {
+    stream_tar_file(path, ArchiveKind::TarXz, writer, options)
+}
+
+/// Append the tar archive compressed with zstd at `path` to `writer`.
+pub fn stream_tar_zst<W: Write>(path: &Path, writer: &mut W, options: &Options) -> Result<Stats> {
+    stream_tar_file(path, ArchiveKind::TarZst, writer, options)
+}
+
+/// Append a single compressed log file (`kind` being one of the single-file kinds, e.g.
+/// [`ArchiveKind::Zst`] for `access.log.zst`) to `writer`, without a tar or zip layer.
+pub fn stream_plain_compressed<W: Write>(
+    path: &Path,
+    kind: ArchiveKind,
+    writer: &mut W,
+    options: &Options,
+) -> Result<Stats> {
+    let mut file = Input::open(path, options)?;
+    let dataset = dataset_stem(path);
+    let reader = decoder(kind, &mut file, options);
+    let mut stats = stream_plain(reader, &dataset, &dataset, writer, options)?;
+    stats.input_sha256 = file.finish()?;
+    Ok(stats)
+}
+
+/// Append the zip inside the compressed stream of the given `kind` at `path` (e.g.
+/// [`ArchiveKind::ZipGz`] for `Android.zip.gz`) to `writer`.
+pub fn stream_compressed_zip<W: Write>(
+    path: &Path,
+    kind: ArchiveKind,
+    writer: &mut W,
+    options: &Options,
+) -> Result<Stats> {
+    let mut file = Input::open(path, options)?;
+    let dataset = dataset_stem(path);
+    let spool = spool(decoder(kind, &mut file, options), "zip")?;
+    let mut stats = stream_zip_reader(spool, &dataset, writer, options)?;
+    stats.input_sha256 = file.finish()?;
+    Ok(stats)
+}
+
+/// Copy `reader` to a temporary file and rewind it, for a `what` archive that needs seeks.
+pub(crate) fn spool(mut reader: impl Read, what: &str) -> Result<File> {
+    let mut spool =
+        tempfile::tempfile().with_context(|| format!("creating temporary file for {what}"))?;
+    io::copy(&mut reader, &mut spool)?;
+    spool.rewind()?;
+    Ok(spool)
+}
+
+/// Append the already decompressed log `reader`, known as `name`, to `writer`.
+fn stream_plain<R: Read, W: Write>(
+    reader: R,
+    name: &str,
+    dataset: &str,
+    writer: &mut W,
+    options: &Options,
+) -> Result<Stats> {
+    let mut out = OutputWriter::new(writer, options, dataset);
+    if options.wants_entry(name) && !out.skips_file() {
+        out.start_file(name)?;
+        copy_lines(reader, name, options, &mut out)?;
+        out.file_done()?;
+    }
+    out.finish()
+}
+
+/// Append every regular file contained in a `.zip` archive to `writer`, in lexicographic order
+/// of the entry names.
+pub fn stream_zip<W: Write>(path: &Path, writer: &mut W, options: &Options) -> Result<Stats> {
+    let mut file = Input::open(path, options)?;
+    let dataset = dataset_stem(path);
+    let mut stats = match options.mmap.then(|| file.map()).flatten() {
+        Some(map) => stream_zip_reader(Cursor::new(&map[..]), &dataset, writer, options)?,
+        None => stream_zip_reader(&mut file, &dataset, writer, options)?,
+    };
+    stats.input_sha256 = file.finish()?;
+    Ok(stats)
+}
+
+/// Like [`stream_zip`], reading the zip from `reader`. `dataset` is the name used for
+/// `--annotate` prefixes.
+pub fn stream_zip_reader<R: Read + Seek, W: Write>(
+    reader: R,
+    dataset: &str,
+    writer: &mut W,
+    options: &Options,
+) -> Result<Stats> {
+    let mut out = OutputWriter::new(writer, options, dataset);
+    let counts = visit_zip(reader, options, |name, mtime, zf| {
+        write_zip_entry(name, mtime, zf, options, &mut out)
+    })?;
+    out.stats += counts;
+    out.finish()
+}
+
+/// Append every regular file contained in a `.7z` archive to `writer`, in archive order. Entries
+/// are separated like those of a zip. The archive is read with seeks, so a 7z read from stdin
+/// is spooled to a temporary file first.
+pub fn stream_7z<W: Write>(path: &Path, writer: &mut W, options: &Options) -> Result<Stats> {
+    let mut file = Input::open(path, options)?;
+    let dataset = dataset_stem(path);
+    let mut stats = stream_7z_reader(&mut file, &dataset, writer, options)?;
+    stats.input_sha256 = file.finish()?;
+    Ok(stats)
+}
+
+/// Like [`stream_7z`], reading the archive from `reader`.
+pub fn stream_7z_reader<R: Read + Seek, W: Write>(
+    reader: R,
+    dataset: &str,
+    writer: &mut W,
+    options: &Options,
+) -> Result<Stats> {
+    let mut out = OutputWriter::new(writer, options, dataset);
+    let counts = visit_7z(reader, options, |name, mtime, entry| {
+        write_zip_entry(name, mtime, entry, options, &mut out)
+    })?;
+    out.stats += counts;
+    out.finish()
+}
+
+/// Archive formats we know how to stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ArchiveKind {
+    #[value(name = "tar.gz")]
+    TarGz,
+    #[value(name = "tar.bz2")]
+    TarBz2,
+    #[value(name = "tar.xz")]
+    TarXz,
+    #[value(name = "tar.zst")]
+    TarZst,
+    Zip,
+    /// A 7-Zip archive. Like zip, it needs random access to the file.
+    #[value(name = "7z")]
+    SevenZ,
+    /// A single gzip-compressed log file, without a tar layer.
+    Gz,
+    /// A single bzip2-compressed log file.
+    Bz2,
+    /// A single xz-compressed log file.
+    Xz,
+    /// A single zstd-compressed log file.
+    Zst,
+    /// A zip archive that was itself gzipped. Spooled to a temporary file once decompressed,
+    /// since the zip needs random access.
+    #[value(name = "zip.gz")]
+    ZipGz,
+    #[value(name = "zip.bz2")]
+    ZipBz2,
+    #[value(name = "zip.xz")]
+    ZipXz,
+    #[value(name = "zip.zst")]
+    ZipZst,
+}
+
+impl ArchiveKind {
+    /// Whether this is a (compressed) tar stream.
+    pub fn is_tar(self) -> bool {
+        matches!(
+            self,
+            ArchiveKind::TarGz | ArchiveKind::TarBz2 | ArchiveKind::TarXz | ArchiveKind::TarZst
+        )
+    }
+
+    /// Whether this is a zip inside a compressed stream.
+    pub fn is_compressed_zip(self) -> bool {
+        matches!(
+            self,
+            ArchiveKind::ZipGz | ArchiveKind::ZipBz2 | ArchiveKind::ZipXz | ArchiveKind::ZipZst
+        )
+    }
+
+    /// The single-file kind compressed the same way as `self`, e.g. `Gz` for `TarGz`.
+    fn compression(self) -> ArchiveKind {
+        match self {
+            ArchiveKind::TarGz | ArchiveKind::ZipGz => ArchiveKind::Gz,
+            ArchiveKind::TarBz2 | ArchiveKind::ZipBz2 => ArchiveKind::Bz2,
+            ArchiveKind::TarXz | ArchiveKind::ZipXz => ArchiveKind::Xz,
+            ArchiveKind::TarZst | ArchiveKind::ZipZst => ArchiveKind::Zst,
+            kind => kind,
+        }
+    }
+
+    /// The tar kind compressed the same way as the single-file kind `self`.
+    fn tarred(self) -> Option<ArchiveKind> {
+        match self {
+            ArchiveKind::Gz => Some(ArchiveKind::TarGz),
+            ArchiveKind::Bz2 => Some(ArchiveKind::TarBz2),
+            ArchiveKind::Xz => Some(ArchiveKind::TarXz),
+            ArchiveKind::Zst => Some(ArchiveKind::TarZst),
+            _ => None,
+        }
+    }
+
+    /// The compressed zip kind compressed the same way as the single-file kind `self`.
+    fn zipped(self) -> Option<ArchiveKind> {
+        match self {
+            ArchiveKind::Gz => Some(ArchiveKind::ZipGz),
+            ArchiveKind::Bz2 => Some(ArchiveKind::ZipBz2),
+            ArchiveKind::Xz => Some(ArchiveKind::ZipXz),
+            ArchiveKind::Zst => Some(ArchiveKind::ZipZst),
+            _ => None,
+        }
+    }
+}
+
+/// Recognized file-name suffixes and the format they imply. Tar and zip suffixes come first so
+/// `.tar.gz` is not taken for a single `.gz` file.
+const ARCHIVE_SUFFIXES: &[(&str, ArchiveKind)] = &[
+    (".tar.gz", ArchiveKind::TarGz),
+    (".tgz", ArchiveKind::TarGz),
+    (".tar.bz2", ArchiveKind::TarBz2),
+    (".tar.xz", ArchiveKind::TarXz),
+    (".tar.zst", ArchiveKind::TarZst),
+    (".zip.gz", ArchiveKind::ZipGz),
+    (".zip.bz2", ArchiveKind::ZipBz2),
+    (".zip.xz", ArchiveKind::ZipXz),
+    (".zip.zst", ArchiveKind::ZipZst),
+    (".zip", ArchiveKind::Zip),
+    (".7z", ArchiveKind::SevenZ),
+    (".gz", ArchiveKind::Gz),
+    (".bz2", ArchiveKind::Bz2),
+    (".xz", ArchiveKind::Xz),
+    (".zst", ArchiveKind::Zst),
+];
+
+/// Like [`str::strip_suffix`], ignoring ASCII case.
+fn strip_suffix_ignore_case<'a>(name: &'a str, suffix: &str) -> Option<&'a str> {
+    let split = name.len().checked_sub(suffix.len())?;
+    (name.is_char_boundary(split) && name[split..].eq_ignore_ascii_case(suffix))
+        .then(|| &name[..split])
+}
+
+/// Match the file name of `p` against [`ARCHIVE_SUFFIXES`], ignoring case.
+/// Returns the stem (file name without the suffix, in its original case) and the archive kind.
+fn split_archive_name(p: &Path) -> Option<(&str, ArchiveKind)> {
+    let fname = p.file_name().and_then(|n| n.to_str())?;
+    ARCHIVE_SUFFIXES.iter().find_map(|&(suffix, kind)| {
+        strip_suffix_ignore_case(fname, suffix).map(|stem| (stem, kind))
+    })
+}
+
+/// Detect the archive format of `p` from its file name, or `None` if it is not an archive.
+pub fn archive_kind(p: &Path) -> Option<ArchiveKind> {
+    split_archive_name(p).map(|(_, kind)| kind)
+}
+
+/// Leading bytes identifying each archive format. Compressed streams are listed as single
+/// files; whether they hold a tar only shows once decompressed.
+const MAGIC_BYTES: &[(&[u8], ArchiveKind)] = &[
+    (b"\x1f\x8b", ArchiveKind::Gz),
+    (b"BZh", ArchiveKind::Bz2),
+    (b"\xfd7zXZ\x00", ArchiveKind::Xz),
+    (b"\x28\xb5\x2f\xfd", ArchiveKind::Zst),
+    (b"PK\x03\x04", ArchiveKind::Zip),
+    // An empty zip is just the end-of-central-directory record.
+    (b"PK\x05\x06", ArchiveKind::Zip),
+    (b"7z\xbc\xaf\x27\x1c", ArchiveKind::SevenZ),
+];
+
+/// Detect the archive format from the first bytes of a file.
+fn sniff_kind(header: &[u8]) -> Option<ArchiveKind> {
+    MAGIC_BYTES
+        .iter()
+        .find(|(magic, _)| header.starts_with(magic))
+        .map(|&(_, kind)| kind)
+}
+
+/// Whether the decompressed stream starting with `block` is a tar archive (POSIX or GNU).
+fn is_tar_header(block: &[u8]) -> bool {
+    block.get(257..262) == Some(b"ustar")
+}
+
+/// Detect the archive format of the file at `p` from its content, falling back to the file name
+/// when the magic bytes match no known format. `None` if it is not an archive either way.
+///
+/// A compressed file counts as a tar archive when its name says so or when its decompressed
+/// start is a tar header, so a tarball named `Spark.gz` is still unpacked. Likewise a
+/// decompressed start with zip magic bytes makes it a compressed zip, whatever the name.
+pub fn detect_archive_kind(p: &Path) -> Result<Option<ArchiveKind>> {
+    let open = || File::open(p).with_context(|| format!("opening {}", p.display()));
+    let mut header = Vec::with_capacity(6);
+    open()?.take(6).read_to_end(&mut header)?;
+    let by_name = archive_kind(p);
+    let Some(sniffed) = sniff_kind(&header) else {
+        return Ok(by_name);
+    };
+    let Some(tarred) = sniffed.tarred() else {
+        return Ok(Some(sniffed));
+    };
+    let zipped = sniffed.zipped();
+    if by_name.is_some_and(|kind| kind == tarred || Some(kind) == zipped) {
+        return Ok(by_name);
+    }
+    let mut block = Vec::with_capacity(512);
+    // A stream that fails to decompress is reported when it is converted.
+    let _ = decoder(sniffed, open()?, &Options::default())
+        .take(512)
+        .read_to_end(&mut block);
+    Ok(Some(if is_tar_header(&block) {
+        tarred
+    } else if sniff_kind(&block) == Some(ArchiveKind::Zip) {
+        zipped.unwrap_or(sniffed)
+    } else {
+        sniffed
+    }))
+}
+
+/// Yields the error it was created with on the first read.
+struct FailingReader(Option<io::Error>);
+
+impl Read for FailingReader {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        match self.0.take() {
+            Some(e) => Err(e),
+            None => Ok(0),
+        }
+    }
+}
+
+/// Derive a stem suitable for naming the output file.
+/// Names without a recognized suffix (e.g. a sniffed `Spark.gz`) lose their last extension.
+pub fn dataset_stem(p: &Path) -> String {
+    match split_archive_name(p) {
+        Some((stem, _)) => stem.to_string(),
+        None => p
+            .file_stem()
+            .and_then(|n| n.to_str())
+            .unwrap_or("")
+            .to_string(),
+    }
+}
+
+/// Append the contents of the archive at `input` to `writer`, picking the format by its magic
+/// bytes (see [`detect_archive_kind`]).
+pub fn convert_archive(input: &Path, writer: &mut impl Write) -> Result<Stats> {
+    convert_archive_with(input, writer, &Options::default())
+}
+
+/// Like [`convert_archive`], but with explicit [`Options`].
+pub fn convert_archive_with(
+    input: &Path,
+    writer: &mut impl Write,
+    options: &Options,
+) -> Result<Stats> {
+    let Some(kind) = detect_archive_kind(input)? else {
+        bail!("{} is not a recognized archive", input.display());
+    };
+    match kind {
+        ArchiveKind::TarGz => stream_tar_gz(input, writer, options),
+        ArchiveKind::TarBz2 => stream_tar_bz2(input, writer, options),
+        ArchiveKind::TarXz => stream_tar_xz(input, writer, options),
+        ArchiveKind::TarZst => stream_tar_zst(input, writer, options),
+        ArchiveKind::Zip => stream_zip(input, writer, options),
+        ArchiveKind::SevenZ => stream_7z(input, writer, options),
+        ArchiveKind::Gz | ArchiveKind::Bz2 | ArchiveKind::Xz | ArchiveKind::Zst => {
+            stream_plain_compressed(input, kind, writer, options)
+        }
+        ArchiveKind::ZipGz | ArchiveKind::ZipBz2 | ArchiveKind::ZipXz | ArchiveKind::ZipZst => {
+            stream_compressed_zip(input, kind, writer, options)
+        }
+    }
+}
+
+/// Like [`convert_archive_with`], additionally writing one `inner/path<TAB>line number<TAB>mtime`
+/// row to `sidecar` for every line written to `writer` (with an empty number for the lines
+/// separating files), so the two can be joined line by line. The modification time is as for
+/// [`Options::annotate`]. Lines must stay in archive order, so
+/// [`Options::tail`] and [`Options::sort`] are rejected.
+pub fn convert_archive_with_sidecar(
+    input: &Path,
+    writer: &mut impl Write,
+    sidecar: &mut impl Write,
+    options: &Options,
+) -> Result<Stats> {
+    if options.tail.is_some() || options.sort != Sort::Off {
+        bail!("a sidecar can't be written for lines reordered by tail or sort");
+    }
+    let Some(kind) = detect_archive_kind(input)? else {
+        bail!("{} is not a recognized archive", input.display());
+    };
+    let dataset = dataset_stem(input);
+    let mut file = Input::open(input, options)?;
+    let mut out = OutputWriter::new(writer, options, &dataset).with_sidecar(sidecar);
+    visit_archive(kind, &mut file, &dataset, options, &mut out)?;
+    let mut stats = out.finish()?;
+    stats.input_sha256 = file.finish()?;
+    Ok(stats)
+}
+
+/// Like [`convert_archive_with`], additionally writing every record to `tee` in `format`, so
+/// one pass over the archive gives it in two formats. [`Stats::bytes`] counts `writer` only.
+/// Held back records would come out of [`Options::tail`] and [`Options::sort`] in one format
+/// only, so they are rejected.
+pub fn convert_archive_tee(
+    input: &Path,
+    writer: &mut impl Write,
+    tee: &mut impl Write,
+    format: Format,
+    options: &Options,
+) -> Result<Stats> {
+    if options.tail.is_some() || options.sort != Sort::Off {
+        bail!("lines reordered by tail or sort can't be teed");
+    }
+    let Some(kind) = detect_archive_kind(input)? else {
+        bail!("{} is not a recognized archive", input.display());
+    };
+    let dataset = dataset_stem(input);
+    let mut file = Input::open(input, options)?;
+    let mut out = OutputWriter::new(writer, options, &dataset).with_tee(tee, format);
+    visit_archive(kind, &mut file, &dataset, options, &mut out)?;
+    let mut stats = out.finish()?;
+    stats.input_sha256 = file.finish()?;
+    Ok(stats)
+}
+
+/// Like [`convert_archive_with`], but hand every line to `rows` together with its dataset, inner
+/// file and line number instead of writing text, e.g. to insert it into a database. Files are
+/// not separated and [`Options::format`] and [`Options::annotate`] don't apply. Lines are
+/// passed on as they are read, so [`Options::tail`] and [`Options::sort`] are rejected.
+pub fn convert_archive_rows(
+    input: &Path,
+    rows: &mut dyn LineSink,
+    options: &Options,
+) -> Result<Stats> {
+    if options.tail.is_some() || options.sort != Sort::Off {
+        bail!("lines reordered by tail or sort can't be passed on as rows");
+    }
+    let Some(kind) = detect_archive_kind(input)? else {
+        bail!("{} is not a recognized archive", input.display());
+    };
+    let dataset = dataset_stem(input);
+    let mut file = Input::open(input, options)?;
+    let mut sink = io::sink();
+    let mut out = OutputWriter::new(&mut sink, options, &dataset).with_rows(rows);
+    visit_archive(kind, &mut file, &dataset, options, &mut out)?;
+    let mut stats = out.finish()?;
+    stats.input_sha256 = file.finish()?;
+    Ok(stats)
+}
+
+/// Feed every selected inner file of the archive `file` of the given `kind` to `out`.
+fn visit_archive<W: Write>(
+    kind: ArchiveKind,
+    file: &mut Input,
+    dataset: &str,
+    options: &Options,
+    out: &mut OutputWriter<'_, W>,
+) -> Result<()> {
+    let counts = match kind {
+        ArchiveKind::Zip => match options.mmap.then(|| file.map()).flatten() {
+            Some(map) => visit_zip(Cursor::new(&map[..]), options, |name, mtime, zf| {
+                write_zip_entry(name, mtime, zf, options, out)
+            })?,
+            None => visit_zip(file, options, |name, mtime, zf| {
+                write_zip_entry(name, mtime, zf, options, out)
+            })?,
+        },
+        ArchiveKind::SevenZ => visit_7z(file, options, |name, mtime, entry| {
+            write_zip_entry(name, mtime, entry, options, out)
+        })?,
+        kind if kind.is_compressed_zip() => {
+            let spool = spool(decoder(kind, file, options), "zip")?;
+            visit_zip(spool, options, |name, mtime, zf| {
+                write_zip_entry(name, mtime, zf, options, out)
+            })?
+        }
+        kind if kind.is_tar() && options.pipeline => pipeline::pipelined(
+            || decoder(kind, file, options),
+            |reader| {
+                visit_tar(reader, options, |name, mtime, entry| {
+                    write_tar_entry(name, mtime, entry, options, out)
+                })
+            },
+        )?,
+        kind if kind.is_tar() => visit_tar(
+            decoder(kind, file, options),
+            options,
+            |name, mtime, entry| write_tar_entry(name, mtime, entry, options, out),
+        )?,
+        kind => {
+            if options.wants_entry(dataset) && !out.skips_file() {
+                out.start_file(dataset)?;
+                copy_lines(decoder(kind, file, options), dataset, options, out)?;
+                out.file_done()?;
+            }
+            Stats::default()
+        }
+    };
+    out.stats += counts;
+    Ok(())
+}
+
+/// Like [`convert_archive_with`], but write every inner file of the archive at `input` to its
+/// own writer instead of concatenating them. `create` is called with the entry path to open the
+/// writer, which is passed to `finish` once the entry is complete. Per-file limits such as
+/// [`Options::head`] apply to each entry separately.
+pub fn convert_archive_split<W: Write>(
+    input: &Path,
+    options: &Options,
+    mut create: impl FnMut(&str) -> Result<W>,
+    mut finish: impl FnMut(W) -> Result<()>,
+) -> Result<Stats> {
+    let Some(kind) = detect_archive_kind(input)? else {
+        bail!("{} is not a recognized archive", input.display());
+    };
+    let dataset = dataset_stem(input);
+    let mut file = Input::open(input, options)?;
+    let mut stats = Stats::default();
+    let mut visit = |name: &str, mtime, entry: &mut dyn Read| -> Result<bool> {
+        // Not even an empty output for the entries after an interrupt or the byte cap.
+        if options.stopped() {
+            return Ok(false);
+        }
+        let mut writer = create(name)?;
+        let mut out = OutputWriter::new(&mut writer, options, &dataset);
+        out.set_mtime(mtime);
+        copy_lines(entry, name, options, &mut out)?;
+        stats += out.finish()?;
+        finish(writer)?;
+        Ok(true)
+    };
+    let counts = match kind {
+        ArchiveKind::Zip => match options.mmap.then(|| file.map()).flatten() {
+            Some(map) => visit_zip(Cursor::new(&map[..]), options, &mut visit)?,
+            None => visit_zip(&mut file, options, &mut visit)?,
+        },
+        ArchiveKind::SevenZ => visit_7z(&mut file, options, &mut visit)?,
+        kind if kind.is_compressed_zip() => {
+            let spool = spool(decoder(kind, &mut file, options), "zip")?;
+            visit_zip(spool, options, &mut visit)?
+        }
+        kind if kind.is_tar() => visit_tar(decoder(kind, &mut file, options), options, &mut visit)?,
+        kind => {
+            if options.wants_entry(&dataset) {
+                visit(&dataset, None, &mut decoder(kind, &mut file, options))?;
+            }
+            Stats::default()
+        }
+    };
+    stats += counts;
+    stats.input_sha256 = file.finish()?;
+    Ok(stats)
+}
+
+/// Count the lines of every inner file of the archive at `input` that passes the entry filters,
+/// without decoding or writing them: [`Stats::lines`] is the number of `\n` bytes (plus an
+/// unterminated last line per file) and [`Stats::bytes`] the decompressed size. Line-level
+/// [`Options`] such as encodings, `dedup` or `grep` are not applied.
+pub fn count_lines(input: &Path, options: &Options) -> Result<Stats> {
+    let Some(kind) = detect_archive_kind(input)? else {
+        bail!("{} is not a recognized archive", input.display());
+    };
+    let dataset = dataset_stem(input);
+    let mut file = Input::open(input, options)?;
+    let mut stats = Stats::default();
+    let mut buf = vec![0; options.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE)];
+    let mut visit = |name: &str, _, entry: &mut dyn Read| -> Result<bool> {
+        let mut last = b'\n';
+        loop {
+            let n = entry
+                .read(&mut buf)
+                .with_context(|| format!("reading {name}"))?;
+            if n == 0 {
+                break;
+            }
+            stats.lines += buf[..n].iter().filter(|&&b| b == b'\n').count() as u64;
+            stats.bytes += n as u64;
+            last = buf[n - 1];
+        }
+        if last != b'\n' {
+            stats.lines += 1;
+        }
+        stats.files += 1;
+        Ok(!options.interrupted())
+    };
+    let counts = match kind {
+        ArchiveKind::Zip => match options.mmap.then(|| file.map()).flatten() {
+            Some(map) => visit_zip(Cursor::new(&map[..]), options, &mut visit)?,
+            None => visit_zip(&mut file, options, &mut visit)?,
+        },
+        ArchiveKind::SevenZ => visit_7z(&mut file, options, &mut visit)?,
+        kind if kind.is_compressed_zip() => {
+            let spool = spool(decoder(kind, &mut file, options), "zip")?;
+            visit_zip(spool, options, &mut visit)?
+        }
+        kind if kind.is_tar() => visit_tar(decoder(kind, &mut file, options), options, &mut visit)?,
+        kind => {
+            if options.wants_entry(&dataset) {
+                visit(&dataset, None, &mut decoder(kind, &mut file, options))?;
+            }
+            Stats::default()
+        }
+    };
+    stats += counts;
+    stats.input_sha256 = file.finish()?;
+    Ok(stats)
+}
+
+/// Turn the path of an inner file into a flat file name: directory separators become `_` and
+/// the `.gz` of an individually gzipped entry, which is written decompressed, is dropped.
+///
+/// Archives may name entries `../../etc/passwd` or `/etc/passwd`, so the result is re-rooted:
+/// a leading `/` or drive prefix (`C:`) and every `.` / `..` component are left out, and the
+/// name can't point outside the directory it is joined to. `None` if nothing is left.
+pub fn flatten_entry_name(name: &str) -> Option<String> {
+    let name = strip_suffix_ignore_case(name, ".gz").unwrap_or(name);
+    let mut components = name
+        .split(['/', '\\'])
+        .filter(|c| !c.is_empty() && *c != "." && *c != "..")
+        .peekable();
+    let mut flat = String::with_capacity(name.len());
+    if let Some(first) = components.peek()
+        && let [drive, b':', ..] = first.as_bytes()
+        && drive.is_ascii_alphabetic()
+    {
+        flat.push_str(&first[2..]);
+        components.next();
+    }
+    for component in components {
+        if !flat.is_empty() {
+            flat.push('_');
+        }
+        flat.push_str(component);
+    }
+    (!flat.is_empty() && flat != "." && flat != "..").then_some(flat)
+}
+
+/// Append the contents of an archive of the given `kind` read from a non-seekable `reader`
+/// (e.g. stdin) to `writer`. Zip and 7z need random access, so they are spooled to a temporary
+/// file first (a compressed zip once decompressed).
+pub fn convert_reader<R: Read>(
+    reader: R,
+    kind: ArchiveKind,
+    dataset: &str,
+    writer: &mut impl Write,
+    options: &Options,
+) -> Result<Stats> {
+    match kind {
+        ArchiveKind::Zip => stream_zip_reader(spool(reader, "zip")?, dataset, writer, options),
+        ArchiveKind::SevenZ => stream_7z_reader(spool(reader, "7z")?, dataset, writer, options),
+        kind if kind.is_compressed_zip() => {
+            let spool = spool(decoder(kind, reader, options), "zip")?;
+            stream_zip_reader(spool, dataset, writer, options)
+        }
+        kind if kind.is_tar() => {
+            stream_tar(decoder(kind, reader, options), dataset, writer, options)
+        }
+        kind => stream_plain(
+            decoder(kind, reader, options),
+            dataset,
+            dataset,
+            writer,
+            options,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_suffixes_ignore_case() {
+        for (name, kind) in [
+            ("HDFS_1.TGZ", ArchiveKind::TarGz),
+            ("Android.Zip", ArchiveKind::Zip),
+            ("Spark.Tar.Gz", ArchiveKind::TarGz),
+            ("BGL.TAR.XZ", ArchiveKind::TarXz),
+        ] {
+            assert_eq!(archive_kind(Path::new(name)), Some(kind), "{name}");
+        }
+        assert_eq!(archive_kind(Path::new("notes.TXT")), None);
+    }
+
+    #[test]
+    fn flattened_entry_names_stay_in_the_output_directory() {
+        for (name, flat) in [
+            ("logs/nested/b.log", Some("logs_nested_b.log")),
+            ("logs/a.log.gz", Some("logs_a.log")),
+            ("../../etc/passwd", Some("etc_passwd")),
+            ("/var/log/./syslog", Some("var_log_syslog")),
+            ("C:\\Windows\\..\\x.log", Some("Windows_x.log")),
+            ("C:x.log", Some("x.log")),
+            ("logs/..", Some("logs")),
+            ("../..", None),
+            ("/", None),
+        ] {
+            assert_eq!(flatten_entry_name(name).as_deref(), flat, "{name}");
+        }
+    }
+
+    #[test]
+    fn zip_crc_errors_are_recognized() {
+        use std::io::Write as _;
+
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let stored =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        writer.start_file("a.log", stored).unwrap();
+        writer.write_all(b"corrupt\n").unwrap();
+        let mut bytes = writer.finish().unwrap().into_inner();
+        let at = bytes.windows(7).position(|w| w == b"corrupt").unwrap();
+        bytes[at] = b'C';
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let e = archive
+            .by_index(0)
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert!(is_crc_mismatch(&e), "{e}");
+        assert!(!is_crc_mismatch(&io::Error::other("premature end")));
+    }
+
+    #[test]
+    fn dataset_stem_keeps_original_case() {
+        assert_eq!(dataset_stem(Path::new("logs/HDFS_1.TGZ")), "HDFS_1");
+        assert_eq!(dataset_stem(Path::new("Android.Zip")), "Android");
+        assert_eq!(dataset_stem(Path::new("Spark.Tar.Gz")), "Spark");
+    }
+}
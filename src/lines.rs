@@ -0,0 +1,135 @@
+//! Pulling the lines of an archive one by one, for consumers that process them themselves.
+
+use std::{
+    path::Path,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{Result, anyhow, bail};
+use crossbeam_channel::{Receiver, Sender, bounded};
+
+use crate::{LineSink, Options, Stats, convert_archive_rows, detect_archive_kind};
+
+/// Lines decoded ahead of the consumer before the decoding thread has to wait.
+const CHANNEL_CAPACITY: usize = 4096;
+
+/// One line of an archive, after the line filters and transformations of [`Options`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogLine {
+    /// Name of the archive without its suffix, as by [`dataset_stem`](crate::dataset_stem).
+    pub dataset: Arc<str>,
+    /// Path of the inner file the line is from; shared by all lines of that file.
+    pub file: Arc<str>,
+    /// Number of the line in `file`, from 1.
+    pub number: u64,
+    /// The line itself, without its line terminator.
+    pub content: String,
+}
+
+/// Iterator over the [`LogLine`]s of an archive.
+///
+/// The archive is decoded on a background thread, a bounded number of lines ahead of the
+/// iterator; dropping it stops the decoding. Errors end the iteration after the lines read
+/// before them.
+///
+/// ```no_run
+/// for line in datasets::LogLines::open("Spark.tar.gz".as_ref())? {
+///     println!("{}", line?.content);
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct LogLines {
+    receiver: Receiver<LogLine>,
+    /// Taken once the channel is drained, to report how decoding ended.
+    worker: Option<JoinHandle<Result<Stats>>>,
+    stats: Option<Stats>,
+}
+
+impl LogLines {
+    /// Start reading the archive at `input` with the default [`Options`].
+    pub fn open(input: &Path) -> Result<Self> {
+        Self::open_with(input, Options::default())
+    }
+
+    /// Like [`LogLines::open`], with explicit `options`. [`Options::format`] and
+    /// [`Options::annotate`] don't apply, and [`Options::tail`] and [`Options::sort`] are
+    /// rejected.
+    pub fn open_with(input: &Path, options: Options) -> Result<Self> {
+        // Fail here rather than on the first line when the archive can't be read at all.
+        if detect_archive_kind(input)?.is_none() {
+            bail!("{} is not a recognized archive", input.display());
+        }
+        let (sender, receiver) = bounded(CHANNEL_CAPACITY);
+        let input = input.to_path_buf();
+        let worker = thread::Builder::new()
+            .name("log-lines".to_string())
+            .spawn(move || {
+                let mut sink = ChannelSink {
+                    sender,
+                    dataset: Arc::from(""),
+                    file: Arc::from(""),
+                };
+                convert_archive_rows(&input, &mut sink, &options)
+            })?;
+        Ok(Self {
+            receiver,
+            worker: Some(worker),
+            stats: None,
+        })
+    }
+
+    /// Counts of the archive, once the iterator returned `None`.
+    pub fn stats(&self) -> Option<&Stats> {
+        self.stats.as_ref()
+    }
+}
+
+impl Iterator for LogLines {
+    type Item = Result<LogLine>;
+
+    fn next(&mut self) -> Option<Result<LogLine>> {
+        if let Ok(line) = self.receiver.recv() {
+            return Some(Ok(line));
+        }
+        // The channel only closes once the worker is done.
+        let worker = self.worker.take()?;
+        match worker.join() {
+            Ok(Ok(stats)) => {
+                self.stats = Some(stats);
+                None
+            }
+            Ok(Err(e)) => Some(Err(e)),
+            Err(_) => Some(Err(anyhow!("the thread decoding the archive panicked"))),
+        }
+    }
+}
+
+/// Sends the lines of [`LogLines`] from the decoding thread.
+struct ChannelSink {
+    sender: Sender<LogLine>,
+    /// Last dataset and file name, reused while they don't change.
+    dataset: Arc<str>,
+    file: Arc<str>,
+}
+
+impl LineSink for ChannelSink {
+    fn write_row(&mut self, dataset: &str, file: &str, number: u64, line: &str) -> Result<()> {
+        if *self.dataset != *dataset {
+            self.dataset = Arc::from(dataset);
+        }
+        if *self.file != *file {
+            self.file = Arc::from(file);
+        }
+        let line = LogLine {
+            dataset: Arc::clone(&self.dataset),
+            file: Arc::clone(&self.file),
+            number,
+            content: line.to_string(),
+        };
+        // Only fails once the iterator was dropped.
+        self.sender
+            .send(line)
+            .map_err(|_| anyhow!("LogLines was dropped"))
+    }
+}
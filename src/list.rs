@@ -0,0 +1,161 @@
+//! Enumerating the inner files of an archive without converting their contents.
+
+use std::{
+    cell::Cell,
+    fs,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::Path,
+    rc::Rc,
+};
+
+use anyhow::{Result, bail};
+use flate2::read::MultiGzDecoder;
+use sevenz_rust::{Password, SevenZReader};
+use tar::{Archive, EntryType};
+use zip::read::ZipArchive;
+
+use crate::{
+    ArchiveKind, Options, ZipEntryType, dataset_stem, decoder, detect_archive_kind,
+    guard::RatioGuard, input::Input, is_special_mode, sevenz_unix_mode, spool,
+    strip_suffix_ignore_case, zip_entry_name, zip_entry_type,
+};
+
+/// One inner file that a conversion would stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// Path inside the archive.
+    pub name: String,
+    /// Uncompressed size in bytes.
+    pub size: u64,
+    /// Compressed size in bytes, for zip entries, single compressed files and individually
+    /// gzipped tar entries. `None` for other tar and 7z entries, which are compressed together
+    /// with the rest of the archive.
+    pub compressed_size: Option<u64>,
+}
+
+impl ArchiveEntry {
+    /// How many times larger the entry is uncompressed, if its compressed size is known.
+    pub fn ratio(&self) -> Option<f64> {
+        self.compressed_size
+            .filter(|&compressed| compressed > 0)
+            .map(|compressed| self.size as f64 / compressed as f64)
+    }
+}
+
+/// List the inner files of the archive at `path` that pass the `include` / `exclude` filters of
+/// `options`, in the order a conversion visits them. Only headers are read; a compressed tar is
+/// still decompressed to get from one header to the next, and its individually gzipped entries
+/// to get their size.
+pub fn list_archive(path: &Path, options: &Options) -> Result<Vec<ArchiveEntry>> {
+    let Some(kind) = detect_archive_kind(path)? else {
+        bail!("{} is not a recognized archive", path.display());
+    };
+    let mut file = Input::open(path, options)?;
+    match kind {
+        ArchiveKind::Zip => match options.mmap.then(|| file.map()).flatten() {
+            Some(map) => list_zip(Cursor::new(&map[..]), options),
+            None => list_zip(&mut file, options),
+        },
+        ArchiveKind::SevenZ => list_7z(&mut file, options),
+        kind if kind.is_compressed_zip() => {
+            list_zip(spool(decoder(kind, &mut file, options), "zip")?, options)
+        }
+        kind if kind.is_tar() => list_tar(decoder(kind, &mut file, options), options),
+        kind => {
+            // The size of a single compressed file is only known once it is decompressed.
+            let name = dataset_stem(path);
+            if !options.wants_entry(&name) {
+                return Ok(Vec::new());
+            }
+            let size = io::copy(&mut decoder(kind, &mut file, options), &mut io::sink())?;
+            Ok(vec![ArchiveEntry {
+                name,
+                size,
+                compressed_size: Some(fs::metadata(path)?.len()),
+            }])
+        }
+    }
+}
+
+fn list_tar<R: Read>(reader: R, options: &Options) -> Result<Vec<ArchiveEntry>> {
+    let mut archive = Archive::new(reader);
+    archive.set_ignore_zeros(true);
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let size = entry.header().size()?;
+        if entry.header().entry_type() != EntryType::Regular
+            || !options.wants_entry(&name)
+            || !options.wants_size(size)
+        {
+            continue;
+        }
+        // An individually gzipped entry is converted decompressed, so that is its size.
+        if strip_suffix_ignore_case(&name, ".gz").is_some() {
+            let consumed = Rc::new(Cell::new(size));
+            let mut decoder =
+                RatioGuard::wrap(MultiGzDecoder::new(entry), consumed, options.max_ratio);
+            entries.push(ArchiveEntry {
+                name,
+                size: io::copy(&mut decoder, &mut io::sink())?,
+                compressed_size: Some(size),
+            });
+        } else {
+            entries.push(ArchiveEntry {
+                name,
+                size,
+                compressed_size: None,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+fn list_zip<R: Read + Seek>(reader: R, options: &Options) -> Result<Vec<ArchiveEntry>> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut entries = Vec::new();
+    for i in 0..archive.len() {
+        let zf = archive.by_index_raw(i)?;
+        let name = zip_entry_name(&zf);
+        if zip_entry_type(&zf) == ZipEntryType::File
+            && options.wants_entry(&name)
+            && options.wants_size(zf.size())
+        {
+            entries.push(ArchiveEntry {
+                name,
+                size: zf.size(),
+                compressed_size: Some(zf.compressed_size()),
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+fn list_7z<R: Read + Seek>(mut reader: R, options: &Options) -> Result<Vec<ArchiveEntry>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.rewind()?;
+    let archive = SevenZReader::new(reader, len, Password::empty())?;
+    let mut files: Vec<_> = archive
+        .archive()
+        .files
+        .iter()
+        .filter(|f| {
+            !f.is_directory()
+                && !sevenz_unix_mode(f).is_some_and(is_special_mode)
+                && options.wants_entry(f.name())
+                && options.wants_size(f.size())
+        })
+        .collect();
+    // Files without content are visited after all compressed ones.
+    files.sort_by_key(|f| !f.has_stream());
+    Ok(files
+        .into_iter()
+        .map(|f| ArchiveEntry {
+            name: f.name().to_string(),
+            size: f.size(),
+            compressed_size: None,
+        })
+        .collect())
+}
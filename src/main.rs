@@ -1,121 +1,2596 @@
-//! Extract every Loghub archive in the current directory into its **own** consolidated text file.
+//! Extract every Loghub archive in the input directory into its **own** consolidated text file.
 //!
 //! * `Spark.tar.gz`  →  `Spark_logs.txt`
 //! * `Android_v2.zip`  →  `Android_v2_logs.txt`
+//! * `Mac.7z`  →  `Mac_logs.txt`
+//! * `HDFS_1.tgz`  →  `HDFS_1_logs.txt`
+//! * `BGL.tar.xz` / `BGL.tar.bz2`  →  `BGL_logs.txt`
+//! * `access.log.zst` (a single compressed file, also `.gz` / `.bz2` / `.xz`)  →  `access.log_logs.txt`
 //!
 //! All log entries remain *one per line*; sub-directories inside the archive are flattened.
 //!
+//! Archives are read from `--input-dir` and outputs written to `--output-dir`; both default to `.`.
+//! That is the `convert` subcommand, run when none is given; `list` and `stats` only read.
 
 use std::{
-    fs::File,
-    io::{self, BufRead, BufReader, BufWriter, Write},
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufWriter, IsTerminal, Write},
     path::{Path, PathBuf},
+    process,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
 };
 
-use anyhow::{Context, Result};
-use flate2::read::GzDecoder;
-use tar::Archive;
+use anyhow::{Context, Result, bail};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use clap::{
+    ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+    builder::RangedU64ValueParser, parser::ValueSource,
+};
+use datasets::{
+    ArchiveKind, ByteBudget, Checkpoint, DEFAULT_BUFFER_SIZE, DEFAULT_CONTINUATION_JOINER, Dedup,
+    Format, InvalidUtf8, LineNumbers, LongLine, MemoryGate, Options, Sort, SqliteSink, Stats,
+    convert_archive_rows, convert_archive_split, convert_archive_tee, convert_archive_with,
+    convert_archive_with_sidecar, convert_reader, count_lines, dataset_stem, detect_archive_kind,
+    estimate_memory, flatten_entry_name, list_archive,
+};
+use encoding_rs::Encoding;
+use flate2::{Compression, write::GzEncoder};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif_log_bridge::LogWrapper;
+use log::{Level, LevelFilter};
+use rayon::prelude::*;
+use regex::{Regex, RegexSet};
+use serde::{Deserialize, Deserializer, Serialize, de};
+use sha2::{Digest, Sha256};
 use walkdir::WalkDir;
-use zip::read::ZipArchive;
-
-/// Append every regular file contained in a `.tar.gz` archive to `writer`.
-/// Adds a single `\n` after each file so logs remain one-per-line.
-fn stream_tar_gz(path: &Path, writer: &mut BufWriter<File>) -> Result<()> {
-    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
-    let decoder = GzDecoder::new(file);
-    let mut archive = Archive::new(decoder);
-
-    for entry in archive.entries()? {
-        let entry = entry?;
-        if entry.header().entry_type().is_file() {
-            let reader = BufReader::new(entry);
-            for line in reader.lines() {
-                // Check if the line is valid UTF-8
-                match line {
-                    Ok(line) => {
-                        // Write the line to the output file
-                        writer.write_all(line.as_bytes())?;
-                        writer.write_all(b"\n")?; // Ensure each log entry is on a new line
-                    }
-                    Err(e) => {
-                        eprintln!("Skipping invalid UTF-8 line ({})", e);
-                        continue;
-                    }
+
+#[derive(Parser, Debug)]
+#[command(
+    about = "Convert Loghub archives into line-separated log files",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Read default flags from this TOML file instead of `loghub.toml` in the working
+    /// directory. Flags given on the command line take precedence.
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Without a subcommand the arguments are those of `convert`.
+    #[command(flatten)]
+    convert: ConvertArgs,
+}
+
+// Parsed once per run, so the size of `ConvertArgs` doesn't matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Convert every archive into line-separated log files (the default).
+    Convert(ConvertArgs),
+    /// Print the path and uncompressed size of every inner file of each archive.
+    List(ListArgs),
+    /// Count the files and lines of each archive without writing any output.
+    Stats(StatsArgs),
+}
+
+/// Which archives are read and which of their inner files.
+#[derive(clap::Args, Debug)]
+struct SourceArgs {
+    /// Archives to read, instead of scanning `--input-dir` (e.g. `*.tar.gz`, or from
+    /// `find -exec`).
+    #[arg(value_name = "ARCHIVE", conflicts_with_all = ["input_dir", "recursive", "follow_symlinks"])]
+    paths: Vec<PathBuf>,
+
+    /// Also read the archives listed in this file, one path per line, or in standard input for
+    /// `-` (e.g. from `git diff --name-only`). Blank lines and lines starting with `#` are
+    /// ignored. An empty list reads nothing rather than scanning `--input-dir`.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["input_dir", "recursive", "follow_symlinks"]
+    )]
+    files_from: Option<PathBuf>,
+
+    /// Directory scanned for `.tar.{gz,bz2,xz,zst}` / `.tgz` / `.zip` / `.7z` archives and single
+    /// `.gz` / `.bz2` / `.xz` / `.zst` files.
+    #[arg(long, default_value = ".")]
+    input_dir: PathBuf,
+
+    /// Descend into sub-directories of `--input-dir` when looking for archives.
+    #[arg(long)]
+    recursive: bool,
+
+    /// Follow symbolic links to directories (and files) while looking for archives. A link
+    /// pointing back at one of its own parent directories is an error.
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Only process archives of this dataset, matched case-insensitively against the archive
+    /// name without its suffix (e.g. `Spark`, `HDFS_1`). Repeatable.
+    #[arg(long, value_name = "NAME")]
+    dataset: Vec<String>,
+
+    /// Skip inner files whose path matches this glob. Repeatable.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Only process inner files whose path matches this glob. Repeatable.
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Abort an archive (removing its partial output) once it decompresses to more than N times
+    /// its compressed size, guarding against zip bombs. `0` disables the check.
+    #[arg(long, value_name = "N", default_value_t = 1000)]
+    max_ratio: u64,
+
+    /// Skip inner files smaller than this many bytes, going by the archive's headers.
+    #[arg(long, value_name = "BYTES")]
+    min_file_size: Option<u64>,
+
+    /// Skip inner files larger than this many bytes, going by the archive's headers.
+    #[arg(long, value_name = "BYTES")]
+    max_file_size: Option<u64>,
+
+    /// Fail a zip archive when one of its entries doesn't match its CRC, before writing any of
+    /// that entry's lines. Without it the mismatch is reported and the archive written anyway.
+    #[arg(long)]
+    strict_crc: bool,
+
+    /// Memory-map zip archives instead of reading them with a seek per entry; faster for zips
+    /// with many entries. Files that can't be mapped are read normally.
+    #[arg(long)]
+    mmap: bool,
+}
+
+impl SourceArgs {
+    /// Whether the archives are given by path instead of found in `--input-dir`.
+    fn is_explicit(&self) -> bool {
+        !self.paths.is_empty() || self.files_from.is_some()
+    }
+
+    /// Add the archives listed in `--files-from` to the positional ones.
+    fn load_files_from(&mut self) -> Result<()> {
+        let Some(list) = &self.files_from else {
+            return Ok(());
+        };
+        let text = if list == Path::new("-") {
+            io::read_to_string(io::stdin().lock()).context("reading the archive list from stdin")?
+        } else {
+            fs::read_to_string(list).with_context(|| format!("reading {}", list.display()))?
+        };
+        self.paths.extend(
+            text.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(PathBuf::from),
+        );
+        Ok(())
+    }
+
+    /// Output stem of the archive at `path`; see [`output_stem`]. Archives given by path are
+    /// named after their file alone.
+    fn output_stem(&self, path: &Path) -> String {
+        if !self.is_explicit() {
+            output_stem(&self.input_dir, path)
+        } else {
+            dataset_stem(path)
+        }
+    }
+
+    /// Options with just the entry filters and the ratio limit set.
+    fn options(&self) -> Result<Options> {
+        Ok(Options {
+            include: glob_set(&self.include)?,
+            exclude: glob_set(&self.exclude)?,
+            max_ratio: (self.max_ratio > 0).then_some(self.max_ratio),
+            mmap: self.mmap,
+            min_file_size: self.min_file_size,
+            max_file_size: self.max_file_size,
+            strict_crc: self.strict_crc,
+            ..Options::default()
+        })
+    }
+}
+
+/// How much is logged to stderr.
+#[derive(clap::Args, Debug)]
+struct LogArgs {
+    /// Also print debug detail, e.g. why files were ignored.
+    #[arg(long, short, conflicts_with = "quiet")]
+    verbose: bool,
+
+    /// Only print errors.
+    #[arg(long, short)]
+    quiet: bool,
+}
+
+/// How the lines of each archive are decoded, filtered and written.
+#[derive(clap::Args, Debug)]
+struct LineArgs {
+    /// Prefix every line with `dataset<TAB>inner/path<TAB>mtime<TAB>`, the last being the inner
+    /// file's modification time in ISO 8601 (empty if the archive has none).
+    #[arg(long)]
+    annotate: bool,
+
+    /// Split the lines of known datasets (HDFS, Spark, BGL, Thunderbird, …) into timestamp,
+    /// level, component and content: tab-separated, or separate fields with `--format ndjson`.
+    /// Lines that don't match keep only their content.
+    #[arg(long)]
+    parse: bool,
+
+    /// Prefix every line with its number and a tab (an `"n"` field with `--format ndjson`),
+    /// counting through each archive or restarting with every inner file.
+    #[arg(long, value_enum, value_name = "MODE")]
+    line_numbers: Option<LineNumbers>,
+
+    /// Output record format.
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// With `--format csv`, start each output with a row of column names.
+    #[arg(long)]
+    csv_header: bool,
+
+    /// Character set of the logs (any WHATWG label, e.g. `windows-1252`); transcoded to UTF-8.
+    #[arg(long, value_name = "LABEL", default_value = "utf-8")]
+    encoding: String,
+
+    /// What to do with lines that are not valid UTF-8: leave them out, keep them with `U+FFFD`
+    /// for the invalid bytes, or write `<invalid UTF-8>` instead.
+    #[arg(long, value_enum, default_value_t = InvalidUtf8::Skip)]
+    invalid_utf8: InvalidUtf8,
+
+    /// Collapse consecutive identical lines into one (like `uniq`).
+    #[arg(long)]
+    dedup: bool,
+
+    /// Drop every line already seen in the same archive. Distinct lines are remembered in memory
+    /// up to `--max-memory` and in a temporary database beyond that.
+    #[arg(long, conflicts_with = "dedup")]
+    dedup_global: bool,
+
+    /// Lines longer than this many bytes are truncated or dropped (see `--on-long-line`).
+    #[arg(long, value_name = "N")]
+    max_line_bytes: Option<usize>,
+
+    /// What to do with lines over `--max-line-bytes`.
+    #[arg(long, value_enum, default_value_t = LongLine::Truncate, requires = "max_line_bytes")]
+    on_long_line: LongLine,
+
+    /// Remove ANSI escape sequences, such as the color codes of captured console output, from
+    /// every line before it is filtered or written.
+    #[arg(long)]
+    strip_ansi: bool,
+
+    /// Re-serialize lines that are JSON objects or arrays compactly, without whitespace between
+    /// tokens, so `--dedup` collapses records that only differ in formatting.
+    #[arg(long)]
+    flatten_json_logs: bool,
+
+    /// Strip trailing whitespace from every line; whitespace-only lines become empty.
+    #[arg(long)]
+    trim_trailing: bool,
+
+    /// Skip empty lines (after `--trim-trailing`) and the blank separator between files.
+    #[arg(long)]
+    drop_empty: bool,
+
+    /// Replace every tab inside log lines by TEXT (e.g. `" "`), so `--annotate` and `--parse`
+    /// columns stay apart. The tabs written between columns are kept.
+    #[arg(long, value_name = "TEXT", allow_hyphen_values = true)]
+    replace_tabs: Option<String>,
+
+    /// Only write lines matching this regular expression. Repeatable; a line matching any of
+    /// them is kept.
+    #[arg(long, value_name = "REGEX")]
+    grep: Vec<String>,
+
+    /// Leave out lines matching this regular expression. Repeatable.
+    #[arg(long, value_name = "REGEX")]
+    grep_invert: Vec<String>,
+
+    /// Join multi-line records such as stack traces: a line not matching this regular
+    /// expression for the start of a record (e.g. `^\d{4}-\d{2}-\d{2} `) is appended to the line
+    /// before it, and filters see the whole record.
+    #[arg(long, value_name = "REGEX")]
+    join_continuations: Option<String>,
+
+    /// Put TEXT between the lines of a `--join-continuations` record.
+    #[arg(
+        long,
+        value_name = "TEXT",
+        default_value = DEFAULT_CONTINUATION_JOINER,
+        allow_hyphen_values = true,
+        requires = "join_continuations"
+    )]
+    joiner: String,
+
+    /// Keep a random sample of the lines: each one with this probability (e.g. `0.01` for
+    /// about 1%).
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction)]
+    sample: Option<f64>,
+
+    /// Seed of `--sample`, to draw the same sample again. Without it a random seed is picked
+    /// and logged.
+    #[arg(long, value_name = "N", requires = "sample")]
+    seed: Option<u64>,
+
+    /// Write only the first N lines of each archive and stop reading it there.
+    #[arg(long, value_name = "N", conflicts_with = "tail")]
+    head: Option<u64>,
+
+    /// Write only the last N lines of each archive (buffered until the archive ends).
+    #[arg(long, value_name = "N")]
+    tail: Option<usize>,
+
+    /// Sort the lines of each archive bytewise. Buffers the whole archive, in memory up to
+    /// `--max-memory` and in temporary files beyond that.
+    #[arg(long, conflicts_with_all = ["head", "tail"])]
+    sort: bool,
+
+    /// Like `--sort`, keeping one copy of each distinct line.
+    #[arg(long, conflicts_with_all = ["head", "tail"])]
+    sort_unique: bool,
+
+    /// Bytes of lines `--sort` and `--dedup-global` each hold in memory before spilling to
+    /// temporary files. Also the memory archives read in parallel share: one whose buffers and
+    /// zip central directory don't fit in what the others left waits for them to finish.
+    #[arg(long, alias = "sort-max-memory", value_name = "BYTES", default_value_t = 1 << 30)]
+    max_memory: usize,
+
+    /// Line written before each inner file, `{name}` being replaced by its path in the archive
+    /// (e.g. `"----- {name} -----"`). Replaces the empty line between files.
+    #[arg(long, value_name = "TEXT", allow_hyphen_values = true)]
+    file_separator: Option<String>,
+
+    /// Don't write an empty line after each inner file; every line already ends in a newline.
+    #[arg(long)]
+    no_trailing_newline_between_files: bool,
+
+    /// End every record with a NUL byte instead of a newline, for `xargs -0` and other
+    /// NUL-delimited tools. An advanced mode: the output is no longer one record per line.
+    #[arg(long)]
+    null_separated: bool,
+
+    /// Keep only lines timestamped at or after this time (`YYYY-MM-DD[ HH:MM:SS]`). Timestamps
+    /// are read in the format of the dataset, e.g. `081109 203518` for HDFS.
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    since: Option<NaiveDateTime>,
+
+    /// Keep only lines timestamped before this time (`YYYY-MM-DD[ HH:MM:SS]`).
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    until: Option<NaiveDateTime>,
+
+    /// With `--since` / `--until` or `--normalize-timestamps`, drop lines without a
+    /// recognizable timestamp instead of keeping them.
+    #[arg(long)]
+    strict_time: bool,
+
+    /// Rewrite the leading timestamp of each line as ISO 8601 (`2015-10-18T18:01:47.978`), so
+    /// the lines of different datasets sort by time. Other lines are left as they are.
+    #[arg(long, conflicts_with = "parse")]
+    normalize_timestamps: bool,
+
+    /// Threads working on one tar archive: `2` decompresses on its own thread while the other
+    /// splits and writes lines. Mostly worth it when a few large archives dominate the run.
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+    threads_per_archive: u8,
+
+    /// Capacity in bytes of the buffers lines are read from and outputs written through.
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = DEFAULT_BUFFER_SIZE,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    buffer_size: usize,
+}
+
+impl LineArgs {
+    /// The `--seed`, or a random one for `--sample` without it.
+    fn sample_seed(&self) -> u64 {
+        match (self.sample, self.seed) {
+            (_, Some(seed)) => seed,
+            (Some(_), None) => {
+                let seed = rand::random();
+                log::info!("sampling with --seed {seed}");
+                seed
+            }
+            (None, None) => 0,
+        }
+    }
+
+    /// Complete the `source` options with the line handling chosen here.
+    fn options(&self, source: Options) -> Result<Options> {
+        Ok(Options {
+            annotate: self.annotate,
+            line_numbers: self.line_numbers,
+            parse: self.parse,
+            format: self.format,
+            csv_header: self.csv_header,
+            encoding: source_encoding(&self.encoding)?,
+            invalid_utf8: self.invalid_utf8,
+            dedup: if self.dedup_global {
+                Dedup::Global
+            } else if self.dedup {
+                Dedup::Consecutive
+            } else {
+                Dedup::Off
+            },
+            max_line_bytes: self.max_line_bytes,
+            on_long_line: self.on_long_line,
+            strip_ansi: self.strip_ansi,
+            flatten_json_logs: self.flatten_json_logs,
+            trim_trailing: self.trim_trailing,
+            drop_empty: self.drop_empty,
+            replace_tabs: self.replace_tabs.clone(),
+            grep: RegexSet::new(&self.grep).context("invalid --grep pattern")?,
+            join_continuations: self
+                .join_continuations
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .context("invalid --join-continuations pattern")?,
+            continuation_joiner: Some(self.joiner.clone()),
+            sample: self.sample,
+            sample_seed: self.sample_seed(),
+            grep_invert: RegexSet::new(&self.grep_invert)
+                .context("invalid --grep-invert pattern")?,
+            head: self.head,
+            tail: self.tail,
+            sort: if self.sort_unique {
+                Sort::Unique
+            } else if self.sort {
+                Sort::Lines
+            } else {
+                Sort::Off
+            },
+            max_memory: Some(self.max_memory),
+            file_separator: self.file_separator.clone(),
+            no_separator_newline: self.no_trailing_newline_between_files,
+            null_separated: self.null_separated,
+            since: self.since,
+            until: self.until,
+            strict_time: self.strict_time,
+            normalize_timestamps: self.normalize_timestamps,
+            pipeline: self.threads_per_archive > 1,
+            buffer_size: Some(self.buffer_size),
+            ..source
+        })
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct ConvertArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// Directory the `*_logs.{txt,ndjson}` files are written to. Created if missing.
+    #[arg(long, default_value = ".")]
+    output_dir: PathBuf,
+
+    /// Number of archives converted in parallel; `0` uses one thread per CPU core.
+    #[arg(long, short, visible_alias = "workers", default_value_t = 0)]
+    jobs: usize,
+
+    /// Write all archives, merged, to standard output instead of per-archive files.
+    #[arg(long, conflicts_with = "output_dir")]
+    stdout: bool,
+
+    /// Gzip-compress each output file (`*_logs.txt.gz`).
+    #[arg(long, conflicts_with = "stdout")]
+    compress: bool,
+
+    /// Gzip level of `--compress`, from 0 (stored, no compression) to 9 (smallest).
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        default_value_t = 6,
+        requires = "compress",
+        value_parser = RangedU64ValueParser::<u32>::new().range(0..=9)
+    )]
+    gzip_level: u32,
+
+    /// Concatenate every archive into the single file `NAME` (default `all_logs.txt`) under
+    /// `--output-dir` instead of one file per archive.
+    #[arg(
+        long,
+        value_name = "NAME",
+        num_args = 0..=1,
+        default_missing_value = "all_logs.txt",
+        conflicts_with_all = ["stdout", "compress"]
+    )]
+    merge_all: Option<PathBuf>,
+
+    /// With `--merge-all`, also write `<name>.index.jsonl` next to the merged file: one JSON
+    /// object per archive with the byte and line offset at which its lines start.
+    #[arg(long, requires = "merge_all", conflicts_with = "null_separated")]
+    merge_index: bool,
+
+    /// Name of each per-archive output, relative to `--output-dir`. `{stem}` is the archive name
+    /// without its suffix, `{ext}` the `--format` extension and `{index}` the archive's position
+    /// in discovery order (from 0). May contain `/`; missing directories are created.
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "{stem}_logs.{ext}",
+        value_parser = parse_output_template
+    )]
+    output_template: String,
+
+    /// TOML file of `stem = "name"` pairs giving the archives whose output would be named after
+    /// `stem` (the `{stem}` of `--output-template`) a name of their own, e.g.
+    /// `Android_v2 = "android"`. Other archives keep the derived name.
+    #[arg(long, value_name = "PATH")]
+    rename_map: Option<PathBuf>,
+
+    /// Like an entry of `--rename-map`, which it takes precedence over. Repeatable.
+    #[arg(long, value_name = "STEM=NAME", value_parser = parse_rename)]
+    rename: Vec<(String, String)>,
+
+    /// `--rename-map` and `--rename` combined, see [`ConvertArgs::load_renames`].
+    #[arg(skip)]
+    renames: BTreeMap<String, String>,
+
+    /// Write every inner file to its own output, `<stem>/<path_with_underscores>` under
+    /// `--output-dir`, instead of merging each archive into one file.
+    #[arg(long, conflicts_with_all = ["stdout", "merge_all", "output_template"])]
+    split_by_file: bool,
+
+    /// Continue an archive's output in a new numbered file (`Spark_logs.0001.txt`,
+    /// `Spark_logs.0002.txt`, …) whenever the current one holds N lines.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["stdout", "merge_all", "split_by_file", "null_separated"],
+        value_parser = RangedU64ValueParser::<u64>::new().range(1..)
+    )]
+    split_lines: Option<u64>,
+
+    /// Like `--split-lines`, starting the next file once the current one holds this many bytes
+    /// (counted before `--compress`).
+    #[arg(
+        long,
+        value_name = "BYTES",
+        conflicts_with_all = ["stdout", "merge_all", "split_by_file"],
+        value_parser = RangedU64ValueParser::<u64>::new().range(1..)
+    )]
+    split_bytes: Option<u64>,
+
+    /// Next to each output, write `<stem>_logs.meta.tsv` with one
+    /// `inner/path<TAB>line number<TAB>mtime` row per output line (the number left empty for
+    /// separator lines), to be joined with `paste`. Keeps `--annotate`-style provenance out of the log text.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "stdout", "merge_all", "split_by_file", "split_lines", "split_bytes", "sort",
+            "sort_unique", "tail",
+        ]
+    )]
+    sidecar: bool,
+
+    /// Also write each archive in FORMAT (by default NDJSON) from the same pass over the
+    /// archive, to the output named with that format's `{ext}`: `Spark_logs.txt` and
+    /// `Spark_logs.ndjson`.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "ndjson",
+        conflicts_with_all = [
+            "stdout", "merge_all", "sqlite", "count_only", "split_by_file", "split_lines",
+            "split_bytes", "sidecar", "resume", "sort", "sort_unique", "tail",
+        ]
+    )]
+    tee: Option<Format>,
+
+    /// Leave outputs that already exist untouched and skip their archives.
+    #[arg(long, overrides_with = "overwrite")]
+    skip_existing: bool,
+
+    /// Truncate and rewrite outputs that already exist (the default).
+    #[arg(long, overrides_with = "skip_existing")]
+    overwrite: bool,
+
+    /// Reconvert archives even if `.loghub-cache.json` in `--output-dir` records them as
+    /// unchanged since their output was written with the same conversion flags.
+    #[arg(long)]
+    force: bool,
+
+    /// Write outputs in place and record in `<output>.checkpoint` after every inner file how
+    /// far they got. Given again after an interrupted or failed run, continue the outputs it
+    /// left from their checkpoint, skipping the inner files they already hold. Needs the same
+    /// archives and flags as the interrupted run.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "stdout", "merge_all", "sqlite", "from_stdin", "count_only", "compress",
+            "checksum_output", "split_by_file", "split_lines", "split_bytes", "sidecar",
+            "skip_existing", "head", "tail", "sort", "sort_unique", "sample", "dedup",
+            "dedup_global",
+        ]
+    )]
+    resume: bool,
+
+    /// Next to each output file, write `<output>.sha256` with its SHA-256 in the format of
+    /// `sha256sum`.
+    #[arg(long, conflicts_with = "stdout")]
+    checksum_output: bool,
+
+    /// Keep the output of archives that produced no lines instead of deleting it.
+    #[arg(long)]
+    keep_empty: bool,
+
+    /// Abort on the first archive that fails instead of continuing with the rest.
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Only list the archives that would be converted and where their output would go.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Only count the lines of every archive, as `\n` bytes, and print the counts. Nothing is
+    /// written and lines are not decoded, so this is much faster than a conversion; line
+    /// filters don't apply.
+    #[arg(long, conflicts_with_all = ["stdout", "merge_all", "split_by_file", "dry_run"])]
+    count_only: bool,
+
+    /// Insert every line into the table `logs(dataset, file, line_no, content)` of this SQLite
+    /// database instead of writing text files, creating both if needed. `line_no` is the number
+    /// of the line in its inner file. Archives are converted one after another; one that fails
+    /// leaves no rows behind.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = [
+            "stdout", "merge_all", "split_by_file", "split_lines", "split_bytes", "sidecar",
+            "compress", "sort", "sort_unique", "tail", "count_only",
+        ]
+    )]
+    sqlite: Option<PathBuf>,
+
+    /// Stop once the outputs of all archives together would exceed N bytes: the archives in
+    /// progress end with the last line that fits, and the remaining ones are not started.
+    /// Counted before `--compress`.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with = "count_only",
+        value_parser = RangedU64ValueParser::<u64>::new().range(1..)
+    )]
+    max_total_bytes: Option<u64>,
+
+    /// After the run, write a JSON array describing every converted archive (counts, output
+    /// path and the archive's SHA-256) to this path.
+    #[arg(long, value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// After the run, write its outcome as JSON to this path, or to standard output for `-`:
+    /// the counts of every archive (or its error), their totals, the number of failures and
+    /// the elapsed time.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["dry_run", "count_only", "from_stdin"]
+    )]
+    summary_json: Option<PathBuf>,
+
+    /// Read a single archive from standard input and write its lines to standard output.
+    #[arg(
+        long,
+        requires = "archive_type",
+        conflicts_with_all = [
+            "paths", "files_from", "input_dir", "recursive", "merge_all", "compress", "dry_run",
+            "sqlite",
+        ]
+    )]
+    from_stdin: bool,
+
+    /// Format of the archive read with `--from-stdin`, which has no file name to go by.
+    #[arg(long, value_enum, requires = "from_stdin")]
+    archive_type: Option<ArchiveKind>,
+
+    #[command(flatten)]
+    lines: LineArgs,
+
+    #[command(flatten)]
+    log: LogArgs,
+
+    /// Show a progress bar per archive (only when stderr is a terminal).
+    #[arg(long)]
+    progress: bool,
+
+    /// Show a bar for the whole run, sized by all archive files together, with the time left
+    /// (only when stderr is a terminal).
+    #[arg(long)]
+    progress_eta: bool,
+}
+
+impl ConvertArgs {
+    /// Read `--rename-map` and add the `--rename` pairs.
+    fn load_renames(&mut self) -> Result<()> {
+        if let Some(path) = &self.rename_map {
+            let text =
+                fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+            self.renames =
+                toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+        }
+        self.renames.extend(self.rename.iter().cloned());
+        if let Some((stem, _)) = self.renames.iter().find(|(_, name)| name.is_empty()) {
+            bail!("--rename-map gives {stem} an empty name");
+        }
+        Ok(())
+    }
+
+    /// Output stem of the archive at `path`, after `--rename-map` / `--rename`.
+    fn output_stem(&self, path: &Path) -> String {
+        let stem = self.source.output_stem(path);
+        self.renames.get(&stem).cloned().unwrap_or(stem)
+    }
+
+    fn output_settings(&self) -> OutputSettings {
+        OutputSettings {
+            compress: self.compress.then(|| Compression::new(self.gzip_level)),
+            buffer_size: self.lines.buffer_size,
+            checksum: self.checksum_output,
+        }
+    }
+
+    /// SHA-256 of the flags that change what an archive's output holds, so that the cache
+    /// doesn't consider an archive converted with other ones unchanged.
+    fn options_key(&self) -> String {
+        let flags = format!(
+            "{:?} {:?} {:?} {} {} {} {:?} {:?} {} {:?} {}",
+            self.lines,
+            self.source.include,
+            self.source.exclude,
+            self.source.max_ratio,
+            self.compress,
+            self.gzip_level,
+            self.split_lines,
+            self.split_bytes,
+            self.sidecar,
+            self.tee,
+            self.checksum_output,
+        );
+        hex(&Sha256::digest(flags))
+    }
+
+    fn options(&self) -> Result<Options> {
+        Ok(Options {
+            hash_input: self.manifest.is_some(),
+            byte_budget: self
+                .max_total_bytes
+                .map(|limit| Arc::new(ByteBudget::new(limit))),
+            ..self.lines.options(self.source.options()?)?
+        })
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct ListArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// Also print the compressed size of each inner file and how many times it shrank, telling
+    /// the most repetitive files apart. Only zip entries, single compressed files and gzipped
+    /// tar entries have one.
+    #[arg(long)]
+    ratio: bool,
+
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct StatsArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// Number of archives read in parallel; `0` uses one thread per CPU core.
+    #[arg(long, short, visible_alias = "workers", default_value_t = 0)]
+    jobs: usize,
+
+    #[command(flatten)]
+    lines: LineArgs,
+
+    #[command(flatten)]
+    log: LogArgs,
+
+    /// Show a progress bar per archive (only when stderr is a terminal).
+    #[arg(long)]
+    progress: bool,
+
+    /// Show a bar for the whole run, sized by all archive files together, with the time left
+    /// (only when stderr is a terminal).
+    #[arg(long)]
+    progress_eta: bool,
+}
+
+/// Config file read from the working directory when `--config` is not given.
+const CONFIG_FILE: &str = "loghub.toml";
+
+/// Defaults from the config file. Keys are named like the flags (`input-dir = "datasets"`);
+/// ones that don't apply to the subcommand being run, or that conflict with a flag given on the
+/// command line (`compress` with `--stdout`), are ignored.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    input_dir: Option<PathBuf>,
+    recursive: Option<bool>,
+    follow_symlinks: Option<bool>,
+    dataset: Option<Vec<String>>,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    max_ratio: Option<u64>,
+    jobs: Option<usize>,
+    #[serde(deserialize_with = "value_enum")]
+    format: Option<Format>,
+    encoding: Option<String>,
+    #[serde(deserialize_with = "value_enum")]
+    invalid_utf8: Option<InvalidUtf8>,
+    annotate: Option<bool>,
+    buffer_size: Option<usize>,
+    output_dir: Option<PathBuf>,
+    output_template: Option<String>,
+    compress: Option<bool>,
+    gzip_level: Option<u32>,
+    checksum_output: Option<bool>,
+}
+
+/// Deserialize a value the way clap parses it for the flag, e.g. `ndjson` for `--format`.
+fn value_enum<'de, D: Deserializer<'de>, T: ValueEnum>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    T::from_str(&name, false)
+        .map(Some)
+        .map_err(de::Error::custom)
+}
+
+impl Config {
+    /// Read `path`, or [`CONFIG_FILE`] if there is one when `path` is `None`.
+    fn load(path: Option<&Path>) -> Result<Config> {
+        let text = match path {
+            Some(path) => fs::read_to_string(path),
+            None => match fs::read_to_string(CONFIG_FILE) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+                read => read,
+            },
+        };
+        let path = path.unwrap_or(Path::new(CONFIG_FILE));
+        let text = text.with_context(|| format!("reading {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// Fill in the arguments of `command` that `matches` didn't get from the command line.
+    /// `cli` is the clap command they were parsed with, whose conflicts the values must obey.
+    fn apply(self, command: &mut Command, matches: &ArgMatches, cli: &clap::Command) -> Result<()> {
+        let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        let conflicts = |id: &str| {
+            let Some(arg) = cli.get_arguments().find(|arg| arg.get_id() == id) else {
+                return false;
+            };
+            let against = |arg: &clap::Arg, other: &clap::Arg| {
+                cli.get_arg_conflicts_with(arg)
+                    .iter()
+                    .any(|conflict| conflict.get_id() == other.get_id())
+            };
+            cli.get_arguments()
+                .filter(|other| given(other.get_id().as_str()))
+                .any(|other| against(arg, other) || against(other, arg))
+        };
+        macro_rules! layer {
+            ($($key:ident => $target:expr),* $(,)?) => {$(
+                if let Some(value) = self.$key.clone()
+                    && !given(stringify!($key))
+                    && !conflicts(stringify!($key))
+                {
+                    $target = value;
                 }
+            )*};
+        }
+        let source = match command {
+            Command::Convert(args) => &mut args.source,
+            Command::List(args) => &mut args.source,
+            Command::Stats(args) => &mut args.source,
+        };
+        layer! {
+            input_dir => source.input_dir,
+            recursive => source.recursive,
+            follow_symlinks => source.follow_symlinks,
+            dataset => source.dataset,
+            include => source.include,
+            exclude => source.exclude,
+            max_ratio => source.max_ratio,
+        }
+        let (lines, jobs) = match command {
+            Command::Convert(args) => (&mut args.lines, &mut args.jobs),
+            Command::Stats(args) => (&mut args.lines, &mut args.jobs),
+            Command::List(_) => return Ok(()),
+        };
+        if self.buffer_size == Some(0) {
+            bail!("buffer-size in the config file must be at least 1");
+        }
+        layer! {
+            jobs => *jobs,
+            format => lines.format,
+            encoding => lines.encoding,
+            invalid_utf8 => lines.invalid_utf8,
+            annotate => lines.annotate,
+            buffer_size => lines.buffer_size,
+        }
+        if let Command::Convert(args) = command {
+            if let Some(template) = &self.output_template {
+                parse_output_template(template).context("output-template in the config file")?;
+            }
+            if self.gzip_level.is_some_and(|level| level > 9) {
+                bail!("gzip-level in the config file must be between 0 and 9");
+            }
+            layer! {
+                output_dir => args.output_dir,
+                output_template => args.output_template,
+                compress => args.compress,
+                gzip_level => args.gzip_level,
+                checksum_output => args.checksum_output,
             }
         }
+        Ok(())
     }
-    Ok(())
 }
 
-/// Append every regular file contained in a `.zip` archive to `writer`.
-fn stream_zip(path: &Path, writer: &mut BufWriter<File>) -> Result<()> {
-    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
-    let mut archive = ZipArchive::new(file)?;
+/// Parse a `--sample` probability.
+fn parse_fraction(value: &str) -> Result<f64> {
+    let fraction: f64 = value.parse().context("not a number")?;
+    if !(0.0..=1.0).contains(&fraction) {
+        bail!("must be between 0 and 1");
+    }
+    Ok(fraction)
+}
 
-    for i in 0..archive.len() {
-        let mut zf = archive.by_index(i)?;
-        if zf.is_file() {
-            io::copy(&mut zf, writer)?;
-            writer.write_all(b"\n")?;
+/// Parse a `--rename` pair.
+fn parse_rename(value: &str) -> Result<(String, String)> {
+    let (old, new) = value.split_once('=').context("expected OLD=NEW")?;
+    if old.is_empty() || new.is_empty() {
+        bail!("names must not be empty");
+    }
+    Ok((old.to_string(), new.to_string()))
+}
+
+/// Parse a `--since` / `--until` bound; a bare date means midnight.
+fn parse_time(value: &str) -> Result<NaiveDateTime> {
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(time);
         }
     }
-    Ok(())
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .context("expected YYYY-MM-DD or YYYY-MM-DD HH:MM:SS")?;
+    Ok(date.and_time(NaiveTime::MIN))
 }
 
-/// Derive a stem suitable for naming the output file.
-fn dataset_stem(p: &Path) -> String {
-    let fname = p.file_name().and_then(|n| n.to_str()).unwrap_or("");
-    if fname.ends_with(".tar.gz") {
-        fname[..fname.len() - 7].to_string() // strip .tar.gz
-    } else if fname.ends_with(".zip") {
-        fname[..fname.len() - 4].to_string() // strip .zip
+/// Look up the `--encoding` label. UTF-8 maps to `None`, which keeps the skip-invalid-lines
+/// behavior instead of transcoding.
+fn source_encoding(label: &str) -> Result<Option<&'static Encoding>> {
+    let Some(encoding) = Encoding::for_label(label.as_bytes()) else {
+        bail!("unknown encoding {label:?}");
+    };
+    Ok((encoding != encoding_rs::UTF_8).then_some(encoding))
+}
+
+/// Compile `patterns` into a single matcher.
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).with_context(|| format!("invalid glob {pattern:?}"))?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Route `log` records to stderr at the level picked by `--verbose` / `--quiet`.
+/// `RUST_LOG` still overrides it.
+///
+/// Returns the set of progress bars when `progress` is requested and stderr is a terminal; log
+/// lines are then printed above the bars instead of through them.
+fn init_logger(args: &LogArgs, progress: bool) -> Option<MultiProgress> {
+    let level = if args.quiet {
+        LevelFilter::Error
+    } else if args.verbose {
+        LevelFilter::Debug
     } else {
-        fname.to_string()
+        LevelFilter::Info
+    };
+    let logger = env_logger::Builder::new()
+        // Dependencies stay at warnings so `--verbose` only adds our own debug output.
+        .filter_level(level.min(LevelFilter::Warn))
+        .filter_module("datasets", level)
+        .parse_default_env()
+        .format(|buf, record| match record.level() {
+            Level::Info => writeln!(buf, "{}", record.args()),
+            level => writeln!(buf, "{}: {}", level.as_str().to_lowercase(), record.args()),
+        })
+        .build();
+    log::set_max_level(logger.filter());
+
+    let progress = (progress && !args.quiet && io::stderr().is_terminal()).then(MultiProgress::new);
+    match &progress {
+        Some(multi) => LogWrapper::new(multi.clone(), logger)
+            .try_init()
+            .expect("logger is only initialized once"),
+        None => log::set_boxed_logger(Box::new(logger)).expect("logger is only initialized once"),
+    }
+    progress
+}
+
+/// How often the `--progress-eta` bar catches up with the bytes read.
+const TOTAL_PROGRESS_TICK: Duration = Duration::from_millis(200);
+
+/// The `--progress-eta` bar over all archives, following [`Options::bytes_read`] from a thread
+/// of its own until dropped.
+struct TotalProgress {
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl TotalProgress {
+    /// Put the bar on top of `multi` and start following `read`, the total being the size of
+    /// all `archives`.
+    fn start(multi: &MultiProgress, archives: &[PathBuf], read: Arc<AtomicU64>) -> Result<Self> {
+        let mut total = 0;
+        for path in archives {
+            total += fs::metadata(path)
+                .with_context(|| format!("reading {}", path.display()))?
+                .len();
+        }
+        let bar = multi.insert(0, ProgressBar::new(total));
+        bar.set_style(
+            ProgressStyle::with_template("{msg:24!} [{bar:40}] {bytes}/{total_bytes} ({eta} left)")
+                .expect("valid progress template")
+                .progress_chars("=> "),
+        );
+        bar.set_message(format!("{} archives", archives.len()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                bar.set_position(read.load(Ordering::Relaxed));
+                thread::sleep(TOTAL_PROGRESS_TICK);
+            }
+            bar.finish_and_clear();
+        });
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
     }
 }
 
-fn main() -> Result<()> {
-    // Scan current directory (non-recursive) for archives.
-    for entry in WalkDir::new(".").max_depth(1) {
-        let entry = entry?;
+impl Drop for TotalProgress {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Start the `--progress-eta` bar, if asked for and there are bars at all, and have `options`
+/// advance it.
+fn total_progress(
+    wanted: bool,
+    multi: Option<&MultiProgress>,
+    archives: &[PathBuf],
+    options: &mut Options,
+) -> Result<Option<TotalProgress>> {
+    let Some(multi) = multi.filter(|_| wanted) else {
+        return Ok(None);
+    };
+    let read = Arc::new(AtomicU64::new(0));
+    options.bytes_read = Some(Arc::clone(&read));
+    TotalProgress::start(multi, archives, read).map(Some)
+}
+
+/// Output stem for the archive at `path` found below `root`.
+/// When recursing, the parent directories relative to `root` are prefixed (joined with `_`) so
+/// `HDFS/logs.tar.gz` and `Spark/logs.tar.gz` don't collide.
+fn output_stem(root: &Path, path: &Path) -> String {
+    let mut parts: Vec<String> = path
+        .parent()
+        .and_then(|parent| parent.strip_prefix(root).ok())
+        .map(|rel| {
+            rel.components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    parts.push(dataset_stem(path));
+    parts.join("_")
+}
+
+/// Collect every archive below `args.input_dir`; only its top level unless `--recursive`, and
+/// only those of the `--dataset`s if any are given. Archives given as arguments are taken as
+/// they are instead.
+fn discover_archives(args: &SourceArgs) -> Result<Vec<PathBuf>> {
+    if args.is_explicit() {
+        let mut archives = Vec::new();
+        for path in &args.paths {
+            if detect_archive_kind(path)?.is_none() {
+                bail!("{} is not a recognized archive", path.display());
+            }
+            if is_selected(args, path) {
+                archives.push(path.clone());
+            }
+        }
+        warn_unmatched_datasets(args, &archives);
+        return Ok(archives);
+    }
+    let mut walker = WalkDir::new(&args.input_dir)
+        .sort_by_file_name()
+        .follow_links(args.follow_symlinks);
+    if !args.recursive {
+        walker = walker.max_depth(1);
+    }
+    let mut archives = Vec::new();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                if let Some(ancestor) = e.loop_ancestor() {
+                    let link = e.path().unwrap_or(ancestor);
+                    bail!(
+                        "symlink loop: {} leads back to {}",
+                        link.display(),
+                        ancestor.display()
+                    );
+                }
+                return Err(e.into());
+            }
+        };
         let path = entry.path();
         if !path.is_file() {
             continue;
         }
-        // Only process files with .tar.gz or .zip extensions
-        if path.extension() != Some("gz".as_ref()) && path.extension() != Some("zip".as_ref()) {
+        if !is_selected(args, path) {
             continue;
         }
+        // Only process files that look like an archive, by content or by name
+        if detect_archive_kind(path)?.is_none() {
+            log::debug!("ignoring {}: not a recognized archive", path.display());
+            continue;
+        }
+        archives.push(path.to_path_buf());
+    }
+    warn_unmatched_datasets(args, &archives);
+    Ok(archives)
+}
+
+/// `archives` found in `--input-dir` without the outputs of earlier runs, which may be archives
+/// themselves (`Spark_logs.txt.gz`): the files below `--output-dir` if it is a directory of its
+/// own inside `--input-dir`, and the outputs the caches in both directories record. Archives
+/// given by path are all kept.
+fn without_outputs(args: &ConvertArgs, archives: Vec<PathBuf>) -> Vec<PathBuf> {
+    if args.source.is_explicit() {
+        return archives;
+    }
+    let input_dir = fs::canonicalize(&args.source.input_dir).ok();
+    let output_dir = fs::canonicalize(&args.output_dir).ok().filter(|dir| {
+        input_dir
+            .as_ref()
+            .is_some_and(|input| !input.starts_with(dir))
+    });
+    let mut cache = load_cache(&args.output_dir);
+    cache.append(&mut load_cache(&args.source.input_dir));
+    let outputs: Vec<PathBuf> = cache
+        .values()
+        .filter_map(|state| fs::canonicalize(&state.output).ok())
+        .chain(output_dir)
+        .collect();
+    archives
+        .into_iter()
+        .filter(|path| {
+            let Ok(canonical) = fs::canonicalize(path) else {
+                return true;
+            };
+            let output = outputs.iter().any(|output| canonical.starts_with(output));
+            if output {
+                log::debug!("ignoring {}: an output of an earlier run", path.display());
+            }
+            !output
+        })
+        .collect()
+}
+
+/// Whether the archive at `path` belongs to one of the `--dataset`s, if any are given.
+fn is_selected(args: &SourceArgs, path: &Path) -> bool {
+    if args.dataset.is_empty() {
+        return true;
+    }
+    let stem = dataset_stem(path);
+    let selected = args
+        .dataset
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(&stem));
+    if !selected {
+        log::debug!("ignoring {}: not a selected dataset", path.display());
+    }
+    selected
+}
+
+fn warn_unmatched_datasets(args: &SourceArgs, archives: &[PathBuf]) {
+    for name in &args.dataset {
+        if !archives
+            .iter()
+            .any(|path| name.eq_ignore_ascii_case(&dataset_stem(path)))
+        {
+            log::warn!("no archive found for dataset {name}");
+        }
+    }
+}
 
-        let out_stem = dataset_stem(path);
-        let out_path = PathBuf::from(format!("{}_logs.txt", out_stem));
-        let out_file =
-            File::create(&out_path).with_context(|| format!("creating {}", out_path.display()))?;
-        let mut writer = BufWriter::new(out_file);
+/// Warn about `--rename-map` / `--rename` stems that no archive has, likely typos.
+fn warn_unused_renames(args: &ConvertArgs, archives: &[PathBuf]) {
+    for stem in args.renames.keys() {
+        if !archives
+            .iter()
+            .any(|path| args.source.output_stem(path) == *stem)
+        {
+            log::warn!("no archive has the stem {stem} to rename");
+        }
+    }
+}
 
-        match path.extension().and_then(|ext| ext.to_str()) {
-            Some("gz")
-                if path
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("")
-                    .ends_with(".tar.gz") =>
-            {
-                eprintln!("→ {}  →  {}", path.display(), out_path.display());
-                stream_tar_gz(path, &mut writer)?;
+/// Fail if two archives would be written to the same output, e.g. `Spark.tar.gz` and
+/// `Spark.zip`, before the second one silently overwrites the first.
+fn check_output_collisions(args: &ConvertArgs, archives: &[PathBuf]) -> Result<()> {
+    let mut outputs: HashMap<PathBuf, &Path> = HashMap::new();
+    for (index, path) in archives.iter().enumerate() {
+        let tee = args
+            .tee
+            .map(|format| tee_output_path(args, index, path, format));
+        for output in [output_path(args, index, path)].into_iter().chain(tee) {
+            if let Some(first) = outputs.insert(output.clone(), path) {
+                bail!(
+                    "{} and {} would both be written to {}; use {{index}} in --output-template to tell them \
+                 apart, or convert them separately",
+                    first.display(),
+                    path.display(),
+                    output.display()
+                );
             }
-            Some("zip") => {
-                eprintln!("→ {}  →  {}", path.display(), out_path.display());
-                stream_zip(path, &mut writer)?;
+        }
+    }
+    Ok(())
+}
+
+/// Path of the per-archive output file for the archive at `path`; its output directory with
+/// `--split-by-file`.
+/// With `--split-lines` / `--split-bytes`, that of the first part.
+fn output_path(args: &ConvertArgs, index: usize, path: &Path) -> PathBuf {
+    let part = (args.split_lines.is_some() || args.split_bytes.is_some()).then_some(1);
+    output_part_path(args, index, path, part)
+}
+
+/// Path of the numbered `part` (from 1) of a split output: `Spark_logs.0002.txt`. The number
+/// goes in front of the `{ext}` of the template, or at the end if it has none.
+fn output_part_path(args: &ConvertArgs, index: usize, path: &Path, part: Option<usize>) -> PathBuf {
+    output_path_as(args, index, path, part, args.lines.format)
+}
+
+/// Path of the `--tee` output in `format` next to the output of the archive at `path`.
+fn tee_output_path(args: &ConvertArgs, index: usize, path: &Path, format: Format) -> PathBuf {
+    output_path_as(args, index, path, None, format)
+}
+
+/// [`output_part_path`] with `{ext}` standing for the extension of `format`.
+fn output_path_as(
+    args: &ConvertArgs,
+    index: usize,
+    path: &Path,
+    part: Option<usize>,
+    format: Format,
+) -> PathBuf {
+    let out_stem = args.output_stem(path);
+    if args.split_by_file {
+        return args.output_dir.join(out_stem);
+    }
+    let part = part.map(|part| format!("{part:04}"));
+    let mut numbered = false;
+    let mut out_name = render_output_template(&args.output_template, |name| match name {
+        "stem" => Some(out_stem.clone()),
+        "ext" => {
+            let ext = format.extension();
+            Some(match &part {
+                Some(part) if !numbered => {
+                    numbered = true;
+                    format!("{part}.{ext}")
+                }
+                _ => ext.to_string(),
+            })
+        }
+        "index" => Some(index.to_string()),
+        _ => None,
+    })
+    .expect("--output-template is validated while parsing");
+    if let Some(part) = part.filter(|_| !numbered) {
+        out_name = format!("{out_name}.{part}");
+    }
+    if args.compress {
+        out_name.push_str(".gz");
+    }
+    args.output_dir.join(out_name)
+}
+
+/// Replace every `{name}` in `template` with `lookup(name)`; `None` if a placeholder is
+/// unknown or a brace is unbalanced.
+fn render_output_template(
+    template: &str,
+    mut lookup: impl FnMut(&str) -> Option<String>,
+) -> Option<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find(['{', '}']) {
+        rendered.push_str(&rest[..open]);
+        let (name, tail) = rest[open..].strip_prefix('{')?.split_once('}')?;
+        rendered.push_str(&lookup(name)?);
+        rest = tail;
+    }
+    rendered.push_str(rest);
+    Some(rendered)
+}
+
+/// Check that `--output-template` only uses known placeholders.
+fn parse_output_template(template: &str) -> Result<String> {
+    render_output_template(template, |name| {
+        matches!(name, "stem" | "ext" | "index").then(String::new)
+    })
+    .context("unbalanced braces or unknown placeholder (known: {stem}, {ext}, {index})")?;
+    Ok(template.to_string())
+}
+
+/// Print what a real run would read and write, without touching the file system.
+fn dry_run(args: &ConvertArgs, archives: &[PathBuf]) -> Result<()> {
+    for (index, path) in archives.iter().enumerate() {
+        let kind = detect_archive_kind(path)?.expect("discovered archives have a known kind");
+        let target = if args.stdout {
+            "<stdout>".to_string()
+        } else if let Some(name) = &args.merge_all {
+            args.output_dir.join(name).display().to_string()
+        } else if let Some(db) = &args.sqlite {
+            db.display().to_string()
+        } else if let Some(format) = args.tee {
+            format!(
+                "{} + {}",
+                output_path(args, index, path).display(),
+                tee_output_path(args, index, path, format).display()
+            )
+        } else {
+            output_path(args, index, path).display().to_string()
+        };
+        println!("→ {} ({:?})  →  {}", path.display(), kind, target);
+    }
+    Ok(())
+}
+
+/// Run `f` on the archive at `path` once the memory it is estimated to take fits in `gate`.
+fn gated<T>(
+    gate: &MemoryGate,
+    path: &Path,
+    options: &Options,
+    f: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let _permit = gate.acquire(estimate_memory(path, options)?);
+    f()
+}
+
+/// Print the line count of every archive and their total.
+fn count_only(args: &ConvertArgs, options: &Options, archives: &[PathBuf]) -> Result<i32> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()?;
+    let gate = MemoryGate::new(args.lines.max_memory as u64);
+    let results: Vec<Result<Stats>> = pool.install(|| {
+        archives
+            .par_iter()
+            .map(|path| gated(&gate, path, options, || count_lines(path, options)))
+            .collect()
+    });
+    let mut total = Stats::default();
+    let mut failures = 0;
+    for (path, result) in archives.iter().zip(results) {
+        match result {
+            Ok(stats) => {
+                println!("{}: {} lines", path.display(), stats.lines);
+                total += stats;
+            }
+            Err(e) => {
+                log::error!("✘ {}: {:#}", path.display(), e);
+                failures += 1;
             }
-            _ => continue,
         }
+    }
+    println!(
+        "Total: {} lines in {} files, {} bytes",
+        total.lines, total.files, total.bytes
+    );
+    if options.interrupted() {
+        log::warn!("Interrupted before all lines were counted.");
+        return Ok(EXIT_INTERRUPTED);
+    }
+    if failures > 0 {
+        return partial_failure(failures, archives.len());
+    }
+    Ok(0)
+}
+
+/// Print the inner files of every archive, one `size  path` line each.
+fn list(archives: &[PathBuf], options: &Options, ratio: bool) -> Result<()> {
+    for path in archives {
+        let entries =
+            list_archive(path, options).with_context(|| format!("listing {}", path.display()))?;
+        println!("{}:", path.display());
+        for entry in entries {
+            if !ratio {
+                println!("{:>12}  {}", entry.size, entry.name);
+                continue;
+            }
+            let compressed = entry
+                .compressed_size
+                .map_or_else(|| "-".to_string(), |size| size.to_string());
+            let ratio = entry
+                .ratio()
+                .map_or_else(|| "-".to_string(), |ratio| format!("{ratio:.1}x"));
+            println!(
+                "{:>12}  {compressed:>12}  {ratio:>7}  {}",
+                entry.size, entry.name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// How an [`OutputFile`] is written.
+#[derive(Clone, Copy)]
+struct OutputSettings {
+    /// Gzip level, if compressed.
+    compress: Option<Compression>,
+    buffer_size: usize,
+    /// Write `<output>.sha256` once the file is finished.
+    checksum: bool,
+}
+
+impl OutputSettings {
+    /// Uncompressed, without a checksum, for the tool's own bookkeeping files.
+    const PLAIN: OutputSettings = OutputSettings {
+        compress: None,
+        buffer_size: DEFAULT_BUFFER_SIZE,
+        checksum: false,
+    };
+}
+
+/// Passes writes through to `inner`, feeding them to `hasher` when there is one.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Option<Sha256>,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The open stream of an [`OutputFile`].
+enum Sink {
+    Plain(BufWriter<HashingWriter<File>>),
+    Gzip(GzEncoder<BufWriter<HashingWriter<File>>>),
+}
+
+/// Path of the `--sidecar` file of the output `path`: `Spark_logs.meta.tsv` for
+/// `Spark_logs.txt(.gz)`.
+fn sidecar_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    path.with_file_name(name).with_extension("meta.tsv")
+}
+
+/// Path of the `--checksum-output` file of the output `path`.
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+/// An output file, gzip-compressed with `--compress` and checksummed with `--checksum-output`.
+/// Unless written [in place](OutputFile::in_place), it is written to a sibling `.tmp` file
+/// that only replaces `path` in [`OutputFile::finish`], so an interrupted or failed conversion
+/// never leaves a truncated output behind; dropping it unfinished deletes the temporary file.
+struct OutputFile {
+    /// `None` once finished.
+    sink: Option<Sink>,
+    path: PathBuf,
+    /// `None` when written in place.
+    tmp: Option<PathBuf>,
+}
+
+impl OutputFile {
+    fn create(path: &Path, settings: OutputSettings) -> Result<Self> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let file = File::create(&tmp).with_context(|| format!("creating {}", tmp.display()))?;
+        Ok(Self::new(file, path, Some(tmp), settings))
+    }
+
+    /// Write to `path` itself, keeping its first `keep` bytes, so that what an interrupted
+    /// conversion wrote stays behind for `--resume`.
+    fn in_place(path: &Path, settings: OutputSettings, keep: u64) -> Result<Self> {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        file.set_len(keep)
+            .with_context(|| format!("truncating {}", path.display()))?;
+        Ok(Self::new(file, path, None, settings))
+    }
+
+    fn new(file: File, path: &Path, tmp: Option<PathBuf>, settings: OutputSettings) -> Self {
+        let file = HashingWriter {
+            inner: file,
+            hasher: settings.checksum.then(Sha256::new),
+        };
+        let writer = BufWriter::with_capacity(settings.buffer_size, file);
+        let sink = if let Some(level) = settings.compress {
+            Sink::Gzip(GzEncoder::new(writer, level))
+        } else {
+            Sink::Plain(writer)
+        };
+        OutputFile {
+            sink: Some(sink),
+            path: path.to_path_buf(),
+            tmp,
+        }
+    }
+
+    fn sink(&mut self) -> &mut Sink {
+        self.sink.as_mut().expect("output file already finished")
+    }
+
+    /// Flush everything to disk, reporting errors that dropping the file would swallow, and move
+    /// the file into place, followed by its checksum file if requested.
+    fn finish(mut self) -> Result<()> {
+        let writer = match self.sink.take().expect("output file already finished") {
+            Sink::Plain(writer) => writer,
+            // `finish` writes the gzip trailer.
+            Sink::Gzip(encoder) => encoder.finish()?,
+        };
+        let hasher = writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .hasher;
+        if let Some(tmp) = &self.tmp {
+            fs::rename(tmp, &self.path)
+                .with_context(|| format!("renaming {} into place", tmp.display()))?;
+        }
+        if let Some(hasher) = hasher {
+            let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+            let sidecar = checksum_path(&self.path);
+            fs::write(&sidecar, format!("{}  {}\n", hex(&hasher.finalize()), name))
+                .with_context(|| format!("writing {}", sidecar.display()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.sink() {
+            Sink::Plain(writer) => writer.write(buf),
+            Sink::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.sink() {
+            Sink::Plain(writer) => writer.flush(),
+            Sink::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        if self.sink.take().is_some()
+            && let Some(tmp) = &self.tmp
+            && let Err(e) = fs::remove_file(tmp)
+        {
+            log::debug!("could not remove {}: {}", tmp.display(), e);
+        }
+    }
+}
+
+/// Spreads one archive's output over numbered files for `--split-lines` / `--split-bytes`.
+/// Every write is a whole record, so files are only ever switched between lines.
+struct RollingWriter<F> {
+    /// Opens the numbered part (from 1).
+    create: F,
+    current: Option<OutputFile>,
+    /// Paths of the parts written and finished so far.
+    finished: Vec<PathBuf>,
+    max_lines: Option<u64>,
+    max_bytes: Option<u64>,
+    lines: u64,
+    bytes: u64,
+}
+
+impl<F: FnMut(usize) -> Result<OutputFile>> RollingWriter<F> {
+    fn new(create: F, max_lines: Option<u64>, max_bytes: Option<u64>) -> Self {
+        RollingWriter {
+            create,
+            current: None,
+            finished: Vec::new(),
+            max_lines,
+            max_bytes,
+            lines: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Finish the current part and start the next.
+    fn roll(&mut self) -> Result<()> {
+        self.finish_current()?;
+        self.current = Some((self.create)(self.finished.len() + 1)?);
+        self.lines = 0;
+        self.bytes = 0;
+        Ok(())
+    }
+
+    fn finish_current(&mut self) -> Result<()> {
+        if let Some(file) = self.current.take() {
+            let path = file.path.clone();
+            file.finish()?;
+            self.finished.push(path);
+        }
+        Ok(())
+    }
+
+    /// Finish the last part; an archive without any lines still gets an (empty) first part.
+    fn finish(&mut self) -> Result<()> {
+        if self.current.is_none() && self.finished.is_empty() {
+            self.roll()?;
+        }
+        self.finish_current()
+    }
+}
+
+impl<F: FnMut(usize) -> Result<OutputFile>> Write for RollingWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let full = self.max_lines.is_some_and(|max| self.lines >= max)
+            || self.max_bytes.is_some_and(|max| self.bytes >= max);
+        if self.current.is_none() || full {
+            self.roll().map_err(io::Error::other)?;
+        }
+        let file = self.current.as_mut().expect("a part was just opened");
+        file.write_all(buf)?;
+        self.lines += buf.iter().filter(|&&b| b == b'\n').count() as u64;
+        self.bytes += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Convert the `index`th archive into its own file under `args.output_dir`, named by
+/// `--output-template`, or with `--split-by-file` into a directory of one file per inner file.
+/// `None` if the archive was skipped: left unconverted by `--skip-existing`, the cache or the
+/// end of the run.
+fn convert_one(
+    args: &ConvertArgs,
+    options: &Options,
+    cache: &Mutex<Cache>,
+    index: usize,
+    path: &Path,
+) -> Result<Option<Stats>> {
+    if options.stopped() {
+        return Ok(None);
+    }
+    let out_path = output_path(args, index, path);
+    // Check before `File::create`, which would already have truncated the file.
+    if args.skip_existing && !args.overwrite && out_path.exists() {
+        log::info!(
+            "↷ skipping {}, {} already exists",
+            path.display(),
+            out_path.display()
+        );
+        return Ok(None);
+    }
+    let source = SourceState::of(path, args.options_key())?;
+    let cached = cache.lock().expect("cache lock").get(path) == Some(&source.with(&out_path));
+    if !args.force && cached && has_content(&out_path) {
+        log::info!(
+            "↷ skipping {}, unchanged since the last run",
+            path.display()
+        );
+        return Ok(None);
+    }
+    let dir = if args.split_by_file {
+        Some(out_path.as_path())
+    } else {
+        out_path.parent()
+    };
+    if let Some(dir) = dir {
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    let resume_from = if args.resume {
+        resume_point(&out_path)?
+    } else {
+        Checkpoint::default()
+    };
+
+    // The logger writes each record in one call, so lines from parallel workers never interleave.
+    log::info!("→ {}  →  {}", path.display(), out_path.display());
+    let mut created = Vec::new();
+    let result = if args.split_by_file {
+        let suffix = if args.compress { ".gz" } else { "" };
+        let mut used = HashSet::new();
+        convert_archive_split(
+            path,
+            options,
+            |name| {
+                let Some(file_name) = flatten_entry_name(name) else {
+                    bail!("refusing to write entry {name:?}: no file name is left of its path");
+                };
+                let file_name = unique_name(&mut used, file_name);
+                let file_path = out_path.join(format!("{file_name}{suffix}"));
+                OutputFile::create(&file_path, args.output_settings())
+            },
+            |file| {
+                created.push(file.path.clone());
+                file.finish()
+            },
+        )
+    } else if args.sidecar {
+        let meta_path = sidecar_path(&out_path);
+        let settings = args.output_settings();
+        let result = OutputFile::create(&out_path, settings).and_then(|mut writer| {
+            let meta_settings = OutputSettings {
+                compress: None,
+                ..settings
+            };
+            let mut sidecar = OutputFile::create(&meta_path, meta_settings)?;
+            let stats = convert_archive_with_sidecar(path, &mut writer, &mut sidecar, options)?;
+            writer.finish()?;
+            sidecar.finish()?;
+            Ok(stats)
+        });
+        if result.is_ok() {
+            created.extend([out_path.clone(), meta_path]);
+        }
+        result
+    } else if let Some(format) = args.tee {
+        let tee_path = tee_output_path(args, index, path, format);
+        let settings = args.output_settings();
+        let result = OutputFile::create(&out_path, settings).and_then(|mut writer| {
+            let mut tee = OutputFile::create(&tee_path, settings)?;
+            let stats = convert_archive_tee(path, &mut writer, &mut tee, format, options)?;
+            writer.finish()?;
+            tee.finish()?;
+            Ok(stats)
+        });
+        if result.is_ok() {
+            created.extend([out_path.clone(), tee_path]);
+        }
+        result
+    } else if args.split_lines.is_some() || args.split_bytes.is_some() {
+        let mut writer = RollingWriter::new(
+            |part| {
+                let part_path = output_part_path(args, index, path, Some(part));
+                OutputFile::create(&part_path, args.output_settings())
+            },
+            args.split_lines,
+            args.split_bytes,
+        );
+        let result = convert_archive_with(path, &mut writer, options).and_then(|stats| {
+            writer.finish()?;
+            Ok(stats)
+        });
+        created.append(&mut writer.finished);
+        result
+    } else if args.resume {
+        convert_resumable(args, options, path, &out_path, resume_from)
+    } else {
+        OutputFile::create(&out_path, args.output_settings()).and_then(|mut writer| {
+            let stats = convert_archive_with(path, &mut writer, options)?;
+            writer.finish()?;
+            Ok(stats)
+        })
+    };
+    let stats = match result {
+        Ok(stats) => stats,
+        Err(e) => {
+            // Inner files already written would pass for a complete conversion later on.
+            remove_outputs(&created);
+            return Err(e);
+        }
+    };
+    if options.stopped() {
+        // Complete as far as it goes, but not to be mistaken for a finished conversion.
+        log::warn!(
+            "{}: {} holds only part of {}{}",
+            if options.interrupted() {
+                "interrupted"
+            } else {
+                "--max-total-bytes reached"
+            },
+            out_path.display(),
+            path.display(),
+            if args.resume {
+                "; run again to continue it"
+            } else {
+                ""
+            }
+        );
+        return Ok(Some(stats));
+    }
+    if stats.lines == 0 && resume_from.lines == 0 {
+        warn_no_lines(path, &stats);
+        if !args.keep_empty {
+            if args.split_by_file {
+                remove_outputs(&created);
+                // Only succeeds if nothing else lives in there.
+                let _ = fs::remove_dir(&out_path);
+            } else if created.is_empty() {
+                remove_outputs(&[out_path]);
+            } else {
+                remove_outputs(&created);
+            }
+            return Ok(Some(stats));
+        }
+    }
+    cache
+        .lock()
+        .expect("cache lock")
+        .insert(path.to_path_buf(), source.with(&out_path));
+    log::info!("✔ wrote {} ({})", out_path.display(), stats);
+    Ok(Some(stats))
+}
+
+/// `name`, or if an earlier entry of the archive was already flattened to it (`a/b.log` and
+/// `a_b.log` both become `a_b.log`), the first of `a_b-2.log`, `a_b-3.log`, … still free.
+fn unique_name(used: &mut HashSet<String>, name: String) -> String {
+    if used.insert(name.clone()) {
+        return name;
+    }
+    let (stem, ext) = match name.rfind('.').filter(|&dot| dot > 0) {
+        Some(dot) => name.split_at(dot),
+        None => (name.as_str(), ""),
+    };
+    let unique = (2..)
+        .map(|n| format!("{stem}-{n}{ext}"))
+        .find(|candidate| !used.contains(candidate))
+        .expect("the counter runs out of names last");
+    log::warn!("two entries flatten to {name}; writing the later one to {unique}");
+    used.insert(unique.clone());
+    unique
+}
+
+/// Path of the `--resume` checkpoint of the output `path`.
+fn checkpoint_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".checkpoint");
+    PathBuf::from(name)
+}
+
+/// Where `--resume` continues the output `path`: at its checkpoint if an interrupted run left
+/// one, otherwise from the start.
+fn resume_point(path: &Path) -> Result<Checkpoint> {
+    let Some(checkpoint) = Checkpoint::load(&checkpoint_path(path))? else {
+        return Ok(Checkpoint::default());
+    };
+    let len = fs::metadata(path).map_or(0, |m| m.len());
+    if len < checkpoint.bytes {
+        log::warn!(
+            "{} is shorter than its checkpoint records, starting it over",
+            path.display()
+        );
+        return Ok(Checkpoint::default());
+    }
+    log::info!(
+        "resuming {} after {} inner files ({} lines)",
+        path.display(),
+        checkpoint.files,
+        checkpoint.lines
+    );
+    Ok(checkpoint)
+}
+
+/// Convert the archive at `path` into `out_path` in place for `--resume`, starting at
+/// `resume_from` and checkpointing after every inner file. The checkpoint is removed once the
+/// archive was converted completely.
+fn convert_resumable(
+    args: &ConvertArgs,
+    options: &Options,
+    path: &Path,
+    out_path: &Path,
+    resume_from: Checkpoint,
+) -> Result<Stats> {
+    let checkpoint = checkpoint_path(out_path);
+    let options = Options {
+        checkpoint: Some(checkpoint.clone()),
+        resume_from,
+        ..options.clone()
+    };
+    let mut writer = OutputFile::in_place(out_path, args.output_settings(), resume_from.bytes)?;
+    let stats = convert_archive_with(path, &mut writer, &options)?;
+    writer.finish()?;
+    if !options.stopped() {
+        remove_outputs(&[checkpoint]);
+    }
+    Ok(stats)
+}
+
+/// Delete the output `files`, logging (but otherwise ignoring) failures.
+fn remove_outputs(files: &[PathBuf]) {
+    for file in files {
+        if let Err(e) = fs::remove_file(file) {
+            log::debug!("could not remove {}: {}", file.display(), e);
+        }
+        let checksum = checksum_path(file);
+        if checksum.exists()
+            && let Err(e) = fs::remove_file(&checksum)
+        {
+            log::debug!("could not remove {}: {}", checksum.display(), e);
+        }
+    }
+}
+
+/// Name of the file in `--output-dir` recording the archives converted so far.
+const CACHE_FILE: &str = ".loghub-cache.json";
 
+/// The state of each converted archive when it was converted, by archive path.
+type Cache = BTreeMap<PathBuf, SourceState>;
+
+/// Size and modification time of an archive, and the output it was converted to with the
+/// flags of [`ConvertArgs::options_key`].
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct SourceState {
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    output: PathBuf,
+    // Missing from caches written before it was recorded, which then never match.
+    #[serde(default)]
+    options: String,
+}
+
+impl SourceState {
+    fn of(path: &Path, options: String) -> Result<SourceState> {
+        let metadata = fs::metadata(path).with_context(|| format!("reading {}", path.display()))?;
+        let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+        Ok(SourceState {
+            size: metadata.len(),
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+            output: PathBuf::new(),
+            options,
+        })
+    }
+
+    fn with(&self, output: &Path) -> SourceState {
+        SourceState {
+            output: output.to_path_buf(),
+            options: self.options.clone(),
+            ..*self
+        }
+    }
+}
+
+/// Whether `path` is a non-empty file or a directory.
+fn has_content(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_dir() || m.len() > 0)
+}
+
+/// Load the cache of `output_dir`. A missing or unreadable cache is empty.
+fn load_cache(output_dir: &Path) -> Cache {
+    let path = output_dir.join(CACHE_FILE);
+    let Ok(contents) = fs::read(&path) else {
+        return Cache::new();
+    };
+    serde_json::from_slice(&contents).unwrap_or_else(|e| {
+        log::warn!("ignoring unreadable {}: {}", path.display(), e);
+        Cache::new()
+    })
+}
+
+fn save_cache(output_dir: &Path, cache: &Cache) -> Result<()> {
+    let path = output_dir.join(CACHE_FILE);
+    let mut file = OutputFile::create(&path, OutputSettings::PLAIN)?;
+    serde_json::to_writer_pretty(&mut file, cache)?;
+    file.write_all(b"\n")?;
+    file.finish()
+}
+
+/// One archive's record in the `--manifest` file.
+#[derive(Serialize)]
+struct ManifestEntry {
+    source: PathBuf,
+    output: String,
+    files: usize,
+    lines: u64,
+    skipped_lines: u64,
+    sha256: Option<String>,
+}
+
+/// One archive's record in the `--summary-json` output: its counts, why it failed, or that it
+/// was skipped.
+#[derive(Serialize)]
+struct ArchiveSummary {
+    source: PathBuf,
+    output: String,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    stats: Option<Stats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    skipped: bool,
+}
+
+/// The `--summary-json` output.
+#[derive(Serialize)]
+struct RunSummary<'a> {
+    archives: &'a [ArchiveSummary],
+    total: Stats,
+    failures: usize,
+    interrupted: bool,
+    elapsed_secs: f64,
+}
+
+/// Where the lines of one archive are in a `--merge-all` output, a line of `--merge-index`.
+#[derive(Serialize)]
+struct IndexEntry {
+    dataset: String,
+    source: PathBuf,
+    byte_offset: u64,
+    line_offset: u64,
+    bytes: u64,
+    lines: u64,
+}
+
+/// Totals over every archive of a run.
+#[derive(Default)]
+struct Summary {
+    stats: Stats,
+    failures: usize,
+    /// Per-archive records, only collected with `--manifest`.
+    manifest: Vec<ManifestEntry>,
+    /// Offsets of the merged archives, only collected with `--merge-index`.
+    index: Vec<IndexEntry>,
+    /// Per-archive outcomes, only collected with `--summary-json`.
+    archives: Vec<ArchiveSummary>,
+}
+
+impl Summary {
+    fn merge(mut self, other: Summary) -> Summary {
+        self.stats += other.stats;
+        self.failures += other.failures;
+        self.manifest.extend(other.manifest);
+        self.index.extend(other.index);
+        self.archives.extend(other.archives);
+        self
+    }
+}
+
+/// Lower-case hex encoding of `bytes`.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Turn the result of converting `path` into `output` into a [`Summary`], logging the error
+/// chain on failure. With `--fail-fast` the error is propagated instead so the run stops. A
+/// skipped archive (`None`) is left out of the manifest and marked in the summary.
+fn tally(
+    args: &ConvertArgs,
+    path: &Path,
+    output: &str,
+    result: Result<Option<Stats>>,
+) -> Result<Summary> {
+    // Only collected with `--summary-json`.
+    let archives = |stats: Option<Stats>, error: Option<String>| {
+        let archive = ArchiveSummary {
+            source: path.to_path_buf(),
+            output: output.to_string(),
+            skipped: stats.is_none() && error.is_none(),
+            stats,
+            error,
+        };
+        args.summary_json
+            .is_some()
+            .then_some(archive)
+            .into_iter()
+            .collect()
+    };
+    match result {
+        Ok(None) => Ok(Summary {
+            archives: archives(None, None),
+            ..Summary::default()
+        }),
+        Ok(Some(stats)) => {
+            let mut manifest = Vec::new();
+            if args.manifest.is_some() {
+                manifest.push(ManifestEntry {
+                    source: path.to_path_buf(),
+                    output: output.to_string(),
+                    files: stats.files,
+                    lines: stats.lines,
+                    skipped_lines: stats.skipped_non_utf8,
+                    sha256: stats.input_sha256.as_ref().map(|digest| hex(digest)),
+                });
+            }
+            Ok(Summary {
+                stats,
+                manifest,
+                archives: archives(Some(stats), None),
+                ..Summary::default()
+            })
+        }
+        Err(e) if args.fail_fast => Err(e.context(format!("converting {}", path.display()))),
+        Err(e) => {
+            log::error!("✘ {}: {:#}", path.display(), e);
+            Ok(Summary {
+                failures: 1,
+                archives: archives(None, Some(format!("{e:#}"))),
+                ..Summary::default()
+            })
+        }
+    }
+}
+
+/// Write the `--summary-json` of a run that started at `start` to `path`, `-` for stdout.
+fn write_summary_json(
+    path: &Path,
+    summary: &Summary,
+    interrupted: bool,
+    start: Instant,
+) -> Result<()> {
+    let run = RunSummary {
+        archives: &summary.archives,
+        total: summary.stats,
+        failures: summary.failures,
+        interrupted,
+        elapsed_secs: start.elapsed().as_secs_f64(),
+    };
+    if path == Path::new("-") {
+        let mut stdout = io::stdout().lock();
+        serde_json::to_writer_pretty(&mut stdout, &run)?;
+        stdout.write_all(b"\n")?;
+        return Ok(stdout.flush()?);
+    }
+    let mut file = OutputFile::create(path, OutputSettings::PLAIN)?;
+    serde_json::to_writer_pretty(&mut file, &run)?;
+    file.write_all(b"\n")?;
+    file.finish()
+}
+
+/// Write the collected `--manifest` records as a JSON array to `path`.
+fn write_manifest(path: &Path, entries: &[ManifestEntry]) -> Result<()> {
+    let mut file = OutputFile::create(path, OutputSettings::PLAIN)?;
+    serde_json::to_writer_pretty(&mut file, entries)?;
+    file.write_all(b"\n")?;
+    file.finish()
+}
+
+/// Write the `--merge-index` records to `path`, one JSON object per line.
+fn write_index(path: &Path, entries: &[IndexEntry]) -> Result<()> {
+    let mut file = OutputFile::create(path, OutputSettings::PLAIN)?;
+    for entry in entries {
+        serde_json::to_writer(&mut file, entry)?;
+        file.write_all(b"\n")?;
+    }
+    file.finish()
+}
+
+/// Counts the bytes and lines written through it.
+struct CountingWriter<W> {
+    inner: W,
+    bytes: u64,
+    lines: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes += n as u64;
+        self.lines += buf[..n].iter().filter(|&&b| b == b'\n').count() as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Stream every archive, in discovery order, into the single `writer` (shown as `label` in
+/// progress messages).
+fn convert_merged<W: Write>(
+    args: &ConvertArgs,
+    options: &Options,
+    archives: &[PathBuf],
+    writer: &mut W,
+    label: &str,
+) -> Result<Summary> {
+    let mut writer = CountingWriter {
+        inner: writer,
+        bytes: 0,
+        lines: 0,
+    };
+    let mut summary = Summary::default();
+    // A CSV header only goes at the very start.
+    let later = Options {
+        csv_header: false,
+        ..options.clone()
+    };
+    for path in archives {
+        if options.stopped() {
+            break;
+        }
+        log::info!("→ {}  →  {}", path.display(), label);
+        let (byte_offset, line_offset) = (writer.bytes, writer.lines);
+        let options = if writer.bytes == 0 { options } else { &later };
+        let result = convert_archive_with(path, &mut writer, options);
+        let mut index = Vec::new();
+        if let Ok(stats) = &result {
+            log::info!("✔ {} ({})", path.display(), stats);
+            if stats.lines == 0 {
+                warn_no_lines(path, stats);
+            }
+            if args.merge_index {
+                index.push(IndexEntry {
+                    dataset: args.output_stem(path),
+                    source: path.clone(),
+                    byte_offset,
+                    line_offset,
+                    bytes: writer.bytes - byte_offset,
+                    lines: writer.lines - line_offset,
+                });
+            }
+        }
+        summary = summary.merge(Summary {
+            index,
+            ..tally(args, path, label, result.map(Some))?
+        });
+    }
+    writer.flush()?;
+    Ok(summary)
+}
+
+/// Insert the lines of every archive, in discovery order, into the `--sqlite` database `db`,
+/// each in a transaction of its own that is rolled back if the archive fails.
+fn convert_sqlite(
+    args: &ConvertArgs,
+    options: &Options,
+    archives: &[PathBuf],
+    db: &Path,
+) -> Result<Summary> {
+    let mut sink = SqliteSink::create(db)?;
+    let label = db.display().to_string();
+    let mut summary = Summary::default();
+    for path in archives {
+        if options.stopped() {
+            break;
+        }
+        log::info!("→ {}  →  {}", path.display(), label);
+        let result = convert_archive_rows(path, &mut sink, options);
+        match &result {
+            Ok(stats) => {
+                sink.commit()?;
+                log::info!("✔ {} ({})", path.display(), stats);
+                if stats.lines == 0 {
+                    warn_no_lines(path, stats);
+                }
+            }
+            Err(_) => sink.rollback()?,
+        }
+        summary = summary.merge(tally(args, path, &label, result.map(Some))?);
+    }
+    sink.finish()?;
+    log::info!("✔ wrote {}", label);
+    Ok(summary)
+}
+
+/// Warn that the archive at `path` produced no lines, telling archives without any (selected)
+/// regular file apart from ones whose files held no lines.
+fn warn_no_lines(path: &Path, stats: &Stats) {
+    if stats.files == 0 {
+        log::warn!(
+            "{} contained no files, or none passed --include / --exclude and the size limits",
+            path.display()
+        );
+    } else {
+        log::warn!(
+            "{} produced no lines; is it misdetected or are all its lines filtered out?",
+            path.display()
+        );
+    }
+}
+
+/// Exit status when some archives failed but others were converted.
+const EXIT_PARTIAL: i32 = 2;
+
+/// Exit status after Ctrl-C, the shell's for a process killed by `SIGINT`.
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Exits with 0 when everything was converted, [`EXIT_PARTIAL`] when only some archives failed,
+/// [`EXIT_INTERRUPTED`] after Ctrl-C and 1 on any other error.
+fn main() {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let code = match configure(cli, &matches).and_then(|command| run(&command)) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            1
+        }
+    };
+    process::exit(code);
+}
+
+/// The command to run, with the arguments not given on the command line taken from the config
+/// file where it has them.
+fn configure(cli: Cli, matches: &ArgMatches) -> Result<Command> {
+    let config = Config::load(cli.config.as_deref())?;
+    let clap_command = Cli::command();
+    let (mut command, matches, clap_command) = match cli.command {
+        Some(command) => {
+            let (name, matches) = matches.subcommand().expect("subcommand was parsed");
+            let clap_command = clap_command
+                .find_subcommand(name)
+                .expect("subcommand is defined")
+                .clone();
+            (command, matches, clap_command)
+        }
+        None => (Command::Convert(cli.convert), matches, clap_command),
+    };
+    config.apply(&mut command, matches, &clap_command)?;
+    match &mut command {
+        Command::Convert(args) => args.source.load_files_from()?,
+        Command::List(args) => args.source.load_files_from()?,
+        Command::Stats(args) => args.source.load_files_from()?,
+    }
+    if let Command::Convert(args) = &mut command {
+        args.load_renames()?;
+        if args.stdout && args.summary_json.as_deref() == Some(Path::new("-")) {
+            bail!("--summary-json - would mix the summary into the --stdout lines");
+        }
+        if let Some(format) = args.tee {
+            if format == args.lines.format {
+                bail!("--tee gives the same format as --format; give it another one");
+            }
+            if !args.output_template.contains("{ext}") {
+                bail!("--tee needs {{ext}} in --output-template to name its outputs apart");
+            }
+        }
+    }
+    Ok(command)
+}
+
+fn run(command: &Command) -> Result<i32> {
+    match command {
+        Command::Convert(args) => convert(args),
+        Command::List(args) => {
+            init_logger(&args.log, false);
+            let options = args.source.options()?;
+            list(&discover_archives(&args.source)?, &options, args.ratio)?;
+            Ok(0)
+        }
+        Command::Stats(args) => stats(args),
+    }
+}
+
+/// Error out when all `total` archives failed, otherwise report the failures and return
+/// [`EXIT_PARTIAL`].
+fn partial_failure(failures: usize, total: usize) -> Result<i32> {
+    let message = format!("{failures} of {total} archives failed");
+    // Nothing was converted at all: as fatal as a startup error.
+    if failures == total {
+        bail!(message);
+    }
+    log::error!("{message}");
+    Ok(EXIT_PARTIAL)
+}
+
+/// Read every archive without writing anything and print its counts.
+fn stats(args: &StatsArgs) -> Result<i32> {
+    let multi = init_logger(&args.log, args.progress || args.progress_eta);
+    let mut options = Options {
+        progress: multi.clone().filter(|_| args.progress),
+        ..args.lines.options(args.source.options()?)?
+    };
+    let archives = discover_archives(&args.source)?;
+    let _total = total_progress(args.progress_eta, multi.as_ref(), &archives, &mut options)?;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()?;
+    let gate = MemoryGate::new(args.lines.max_memory as u64);
+    let results: Vec<Result<Stats>> = pool.install(|| {
+        archives
+            .par_iter()
+            .map(|path| {
+                gated(&gate, path, &options, || {
+                    convert_archive_with(path, &mut io::sink(), &options)
+                })
+            })
+            .collect()
+    });
+
+    let mut total = Stats::default();
+    let mut failures = 0;
+    for (path, result) in archives.iter().zip(results) {
+        match result {
+            Ok(stats) => {
+                println!("{}: {}", path.display(), stats);
+                total += stats;
+            }
+            Err(e) => {
+                log::error!("✘ {}: {:#}", path.display(), e);
+                failures += 1;
+            }
+        }
+    }
+    println!("Total: {total}");
+    if failures > 0 {
+        return partial_failure(failures, archives.len());
+    }
+    Ok(0)
+}
+
+fn convert(args: &ConvertArgs) -> Result<i32> {
+    let start = Instant::now();
+    let multi = init_logger(&args.log, args.progress || args.progress_eta);
+    let interrupt = Arc::new(AtomicBool::new(false));
+    let handler_flag = Arc::clone(&interrupt);
+    ctrlc::set_handler(move || {
+        // A second Ctrl-C doesn't wait for the current outputs.
+        if handler_flag.swap(true, Ordering::Relaxed) {
+            process::exit(EXIT_INTERRUPTED);
+        }
+        log::warn!("Interrupted, finishing the outputs in progress (Ctrl-C again to abort)");
+    })
+    .context("installing the Ctrl-C handler")?;
+    let mut options = Options {
+        progress: multi.clone().filter(|_| args.progress),
+        interrupt: Some(interrupt),
+        ..args.options()?
+    };
+
+    if let Some(kind) = args.archive_type.filter(|_| args.from_stdin) {
+        let mut writer = BufWriter::with_capacity(args.lines.buffer_size, io::stdout());
+        let stats = convert_reader(io::stdin().lock(), kind, "stdin", &mut writer, &options)?;
         writer.flush()?;
-        eprintln!("✔ wrote {}", out_path.display());
+        if options.interrupted() {
+            log::warn!("Interrupted before the whole archive was converted.");
+            return Ok(EXIT_INTERRUPTED);
+        }
+        log::info!("✔ <stdin> ({})", stats);
+        return Ok(0);
     }
 
-    eprintln!("All datasets processed.");
-    Ok(())
+    let archives = without_outputs(args, discover_archives(&args.source)?);
+    warn_unused_renames(args, &archives);
+    let per_archive = !args.stdout && args.merge_all.is_none() && args.sqlite.is_none();
+    if per_archive && !args.count_only {
+        check_output_collisions(args, &archives)?;
+    }
+
+    if args.dry_run {
+        dry_run(args, &archives)?;
+        return Ok(0);
+    }
+    let _total = total_progress(args.progress_eta, multi.as_ref(), &archives, &mut options)?;
+    if args.count_only {
+        return count_only(args, &options, &archives);
+    }
+
+    let summary = if args.stdout {
+        let mut writer = BufWriter::with_capacity(args.lines.buffer_size, io::stdout());
+        convert_merged(args, &options, &archives, &mut writer, "<stdout>")?
+    } else if let Some(db) = &args.sqlite {
+        convert_sqlite(args, &options, &archives, db)?
+    } else if let Some(name) = &args.merge_all {
+        fs::create_dir_all(&args.output_dir)
+            .with_context(|| format!("creating {}", args.output_dir.display()))?;
+        let out_path = args.output_dir.join(name);
+        let settings = OutputSettings {
+            compress: None,
+            ..args.output_settings()
+        };
+        let mut writer = OutputFile::create(&out_path, settings)?;
+        let label = out_path.display().to_string();
+        let summary = convert_merged(args, &options, &archives, &mut writer, &label)?;
+        writer.finish()?;
+        log::info!("✔ wrote {}", out_path.display());
+        if args.merge_index {
+            let index_path = out_path.with_extension("index.jsonl");
+            write_index(&index_path, &summary.index)?;
+            log::info!("✔ wrote index {}", index_path.display());
+        }
+        summary
+    } else {
+        fs::create_dir_all(&args.output_dir)
+            .with_context(|| format!("creating {}", args.output_dir.display()))?;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(args.jobs)
+            .build()?;
+        let cache = Mutex::new(load_cache(&args.output_dir));
+        let gate = MemoryGate::new(args.lines.max_memory as u64);
+        let summary = pool.install(|| {
+            archives
+                .par_iter()
+                .enumerate()
+                .map(|(index, path)| {
+                    let output = output_path(args, index, path).display().to_string();
+                    tally(
+                        args,
+                        path,
+                        &output,
+                        gated(&gate, path, &options, || {
+                            convert_one(args, &options, &cache, index, path)
+                        }),
+                    )
+                })
+                .try_reduce(Summary::default, |a, b| Ok(a.merge(b)))
+        });
+        // Also record what was converted before a `--fail-fast` abort.
+        save_cache(&args.output_dir, &cache.into_inner().expect("cache lock"))?;
+        summary?
+    };
+
+    log::info!("Total: {}", summary.stats);
+    if let Some(manifest) = &args.manifest {
+        write_manifest(manifest, &summary.manifest)?;
+        log::info!("✔ wrote manifest {}", manifest.display());
+    }
+    if let Some(path) = &args.summary_json {
+        write_summary_json(path, &summary, options.interrupted(), start)?;
+    }
+    if options.interrupted() {
+        log::warn!("Interrupted before all archives were converted.");
+        return Ok(EXIT_INTERRUPTED);
+    }
+    let capped = options.byte_budget.as_ref().filter(|b| b.is_exhausted());
+    if let Some(budget) = capped {
+        log::warn!(
+            "Stopped at the --max-total-bytes cap after writing {} bytes.",
+            budget.used()
+        );
+    }
+    if summary.failures > 0 {
+        return partial_failure(summary.failures, archives.len());
+    }
+    if capped.is_none() {
+        log::info!("All datasets processed.");
+    }
+    Ok(0)
 }
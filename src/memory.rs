@@ -0,0 +1,101 @@
+//! Bounding the memory of archives converted at the same time.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    sync::{Condvar, Mutex},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{ArchiveKind, DEFAULT_BUFFER_SIZE, Options, detect_archive_kind};
+
+/// Bytes a parsed zip entry takes besides its name and extra fields.
+const ZIP_ENTRY_OVERHEAD: u64 = 256;
+
+/// Signature of the end of central directory record, which is 22 bytes plus a comment.
+const EOCD_SIGNATURE: &[u8] = b"PK\x05\x06";
+
+/// A memory budget shared by the archives converted in parallel: each one waits until its
+/// estimate (see [`estimate_memory`]) fits in what the running ones left over.
+#[derive(Debug)]
+pub struct MemoryGate {
+    limit: u64,
+    used: Mutex<u64>,
+    released: Condvar,
+}
+
+/// Memory taken from a [`MemoryGate`], given back when dropped.
+#[derive(Debug)]
+pub struct MemoryPermit<'a> {
+    gate: &'a MemoryGate,
+    bytes: u64,
+}
+
+impl MemoryGate {
+    /// A budget of `limit` bytes, none of them taken yet.
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Block until `bytes` fit in the budget and take them. More than the whole budget is
+    /// taken as all of it, so such an archive waits to run alone instead of forever.
+    pub fn acquire(&self, bytes: u64) -> MemoryPermit<'_> {
+        let bytes = bytes.min(self.limit);
+        let mut used = self.used.lock().expect("memory gate lock");
+        while *used + bytes > self.limit {
+            used = self.released.wait(used).expect("memory gate lock");
+        }
+        *used += bytes;
+        MemoryPermit { gate: self, bytes }
+    }
+}
+
+impl Drop for MemoryPermit<'_> {
+    fn drop(&mut self) {
+        *self.gate.used.lock().expect("memory gate lock") -= self.bytes;
+        self.gate.released.notify_all();
+    }
+}
+
+/// Rough estimate of the memory converting the archive at `path` takes, besides the budgets of
+/// [`Options::max_memory`]: the read buffer and, for a zip, its central directory, which is
+/// held in memory whole while the archive is open.
+pub fn estimate_memory(path: &Path, options: &Options) -> Result<u64> {
+    let buffer = options.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE) as u64;
+    if detect_archive_kind(path)? != Some(ArchiveKind::Zip) {
+        return Ok(buffer);
+    }
+    Ok(buffer + zip_directory_memory(path)?)
+}
+
+/// Memory the central directory of the zip at `path` takes once parsed, going by the size and
+/// entry count in its end of central directory record. Names are kept both raw and decoded, so
+/// the records count twice. A zip64 archive, whose record only points to the real one, is
+/// charged its whole size; one without a record fails to open later and is charged nothing.
+fn zip_directory_memory(path: &Path) -> Result<u64> {
+    let mut file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let len = file.metadata()?.len();
+    let tail_len = len.min(22 + u64::from(u16::MAX));
+    file.seek(SeekFrom::End(-(tail_len as i64)))?;
+    let mut tail = Vec::with_capacity(tail_len as usize);
+    file.take(tail_len).read_to_end(&mut tail)?;
+    let Some(record) = tail
+        .windows(EOCD_SIGNATURE.len())
+        .rposition(|window| window == EOCD_SIGNATURE)
+        .and_then(|start| tail.get(start..start + 22))
+    else {
+        return Ok(0);
+    };
+    let entries = u16::from_le_bytes([record[10], record[11]]);
+    let size = u32::from_le_bytes([record[12], record[13], record[14], record[15]]);
+    if entries == u16::MAX || size == u32::MAX {
+        return Ok(len);
+    }
+    Ok(2 * u64::from(size) + u64::from(entries) * ZIP_ENTRY_OVERHEAD)
+}
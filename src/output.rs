@@ -0,0 +1,728 @@
+//! Formatting of emitted log lines.
+
+use std::{borrow::Cow, collections::VecDeque, io::Write, mem};
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use csv::{Terminator, WriterBuilder};
+use rand::{RngExt, SeedableRng, rngs::StdRng};
+use regex::Regex;
+use serde::{Serialize, de::IgnoredAny};
+
+use crate::{
+    Checkpoint, Dedup, LineNumbers, LongLine, Options, Stats,
+    dedup::SeenLines,
+    parse::{Fields, LineParser, line_parser},
+    sort::{Sort, Sorter},
+    time::{TimestampFormat, timestamp_format},
+};
+
+/// Shape of every emitted record.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// The raw log line, optionally prefixed by `--annotate`.
+    #[default]
+    Text,
+    /// One `{"dataset","file","mtime","line"}` JSON object per line (with `--parse`, the
+    /// line's fields instead of `line`). `mtime` is left out when the archive has none.
+    Ndjson,
+    /// `dataset,file,mtime,line` rows, quoted where needed (with `--parse`, the line's fields
+    /// instead of `line`). Line numbers go in a leading `n` column.
+    Csv,
+}
+
+impl Format {
+    /// File extension of outputs written in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Text => "txt",
+            Format::Ndjson => "ndjson",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+/// Receives every emitted line as a row instead of formatted text, see
+/// [`convert_archive_rows`](crate::convert_archive_rows).
+pub trait LineSink {
+    /// Take line `number` (from 1) of the inner file `file` of `dataset`, after all line filters
+    /// and transformations.
+    fn write_row(&mut self, dataset: &str, file: &str, number: u64, line: &str) -> Result<()>;
+}
+
+/// CSI escape sequences, which include the SGR ones setting terminal colors, for
+/// [`Options::strip_ansi`].
+const ANSI_ESCAPE: &str = r"\x1b\[[0-?]*[ -/]*[@-~]";
+
+/// ISO 8601 rendering of the modification times of inner files.
+const MTIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// Appended to lines cut short by [`LongLine::Truncate`].
+const TRUNCATION_MARKER: &str = "…";
+
+/// Cut `line` to at most `max` bytes (on a char boundary) and append [`TRUNCATION_MARKER`].
+fn truncate(line: &str, max: usize) -> String {
+    let mut end = max;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &line[..end], TRUNCATION_MARKER)
+}
+
+/// `line` without whitespace between its tokens, if it holds a JSON object or array. Keys stay
+/// in their order and numbers as written. `None` for other lines and for already compact ones.
+fn compact_json(line: &str) -> Option<String> {
+    if !line.trim_start().starts_with(['{', '['])
+        || serde_json::from_str::<IgnoredAny>(line).is_err()
+    {
+        return None;
+    }
+    let mut compact = String::with_capacity(line.len());
+    let (mut in_string, mut escaped) = (false, false);
+    for c in line.chars() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if matches!(c, ' ' | '\t' | '\n' | '\r') {
+            continue;
+        }
+        compact.push(c);
+    }
+    (compact.len() != line.len()).then_some(compact)
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<u64>,
+    dataset: &'a str,
+    file: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mtime: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    level: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    component: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<&'a str>,
+}
+
+/// Append one CSV row of `fields`, without a line terminator, to `buf`.
+fn write_csv_row<'f>(
+    builder: &WriterBuilder,
+    buf: &mut Vec<u8>,
+    fields: impl IntoIterator<Item = &'f str>,
+) -> Result<()> {
+    let mut row = builder.from_writer(&mut *buf);
+    for field in fields {
+        row.write_field(field)?;
+    }
+    // Only ending the record closes a quoted last field; the terminator is added like for the
+    // other formats.
+    row.write_record(None::<&[u8]>)?;
+    row.flush()?;
+    drop(row);
+    buf.pop();
+    Ok(())
+}
+
+/// Take `bytes` from the [`Options::byte_budget`], if any; `false` if they don't fit.
+fn spend(options: &Options, bytes: usize) -> bool {
+    options
+        .byte_budget
+        .as_ref()
+        .is_none_or(|budget| budget.spend(bytes as u64))
+}
+
+/// Writes lines of one archive to `writer` in the configured [`Format`], counting what it
+/// writes in `stats`.
+pub(crate) struct OutputWriter<'a, W: Write> {
+    writer: &'a mut W,
+    options: &'a Options,
+    dataset: &'a str,
+    /// Set up for [`Format::Csv`] rows.
+    csv: WriterBuilder,
+    /// Whether the `--csv-header` row is still to be written.
+    csv_header: bool,
+    /// Receives every record a second time in another format, for `--tee`.
+    tee: Option<(&'a mut dyn Write, Format)>,
+    /// Scratch space of the records for `tee`.
+    tee_buf: Vec<u8>,
+    /// Scratch space each record is formatted into before being written.
+    buf: Vec<u8>,
+    /// Last line written, for `Dedup::Consecutive`.
+    last_line: Option<String>,
+    /// Every line written so far, for `Dedup::Global`.
+    seen: SeenLines,
+    /// Formatted records held back for `--tail`, oldest first.
+    tail: VecDeque<Vec<u8>>,
+    /// Records held back for `--sort`.
+    sorter: Option<Sorter>,
+    /// How to read line timestamps for `--since` / `--until` / `--normalize-timestamps`.
+    timestamps: Option<&'static TimestampFormat>,
+    /// How to split lines for `--parse`.
+    parser: Option<LineParser>,
+    /// Matches what `--strip-ansi` removes.
+    ansi: Option<Regex>,
+    /// Decides which lines `--sample` keeps.
+    sampler: Option<(f64, StdRng)>,
+    /// Number of the last line emitted, for `--line-numbers`.
+    line_number: u64,
+    /// Inner files started so far, including those skipped for [`Options::resume_from`].
+    files_seen: usize,
+    /// Modification time of the current inner file, already formatted.
+    mtime: Option<String>,
+    /// Receives a `file<TAB>line number<TAB>mtime` row for every line written, the number
+    /// blank for separators.
+    sidecar: Option<&'a mut dyn Write>,
+    /// Takes the lines instead of `writer`, for [`LineSink`]s.
+    rows: Option<&'a mut dyn LineSink>,
+    pub(crate) stats: Stats,
+}
+
+impl<'a, W: Write> OutputWriter<'a, W> {
+    pub(crate) fn new(writer: &'a mut W, options: &'a Options, dataset: &'a str) -> Self {
+        let timestamps = if options.since.is_some()
+            || options.until.is_some()
+            || options.normalize_timestamps
+        {
+            let format = timestamp_format(dataset);
+            if format.is_none() {
+                log::warn!("no timestamp format known for dataset {dataset}; lines are not dated");
+            }
+            format
+        } else {
+            None
+        };
+        let parser = if options.parse {
+            let parser = line_parser(dataset);
+            if parser.is_none() {
+                log::warn!("no line format known for dataset {dataset}; lines are not parsed");
+            }
+            parser
+        } else {
+            None
+        };
+        let mut csv = WriterBuilder::new();
+        // Rows are short; the default 8 KiB would be allocated for every one of them.
+        csv.has_headers(false)
+            .terminator(Terminator::Any(b'\n'))
+            .buffer_capacity(256);
+        Self {
+            writer,
+            options,
+            dataset,
+            csv,
+            // A resumed output already has its header.
+            csv_header: options.csv_header && options.resume_from.files == 0,
+            tee: None,
+            tee_buf: Vec::new(),
+            buf: Vec::new(),
+            last_line: None,
+            seen: SeenLines::new(options.max_memory),
+            tail: VecDeque::new(),
+            sorter: match options.sort {
+                Sort::Off => None,
+                sort => Some(Sorter::new(
+                    sort == Sort::Unique,
+                    options.max_memory,
+                    options.terminator(),
+                )),
+            },
+            timestamps,
+            parser,
+            ansi: options
+                .strip_ansi
+                .then(|| Regex::new(ANSI_ESCAPE).expect("the escape pattern is valid")),
+            sampler: options
+                .sample
+                .map(|p| (p, StdRng::seed_from_u64(options.sample_seed))),
+            line_number: options.resume_from.lines,
+            files_seen: 0,
+            mtime: None,
+            sidecar: None,
+            rows: None,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Also write a row describing each output line to `sidecar`.
+    pub(crate) fn with_sidecar(mut self, sidecar: &'a mut dyn Write) -> Self {
+        self.sidecar = Some(sidecar);
+        self
+    }
+
+    /// Also write every record, file separators and `--csv-header` row to `tee`, in `format`.
+    /// Only the records for `writer` are counted.
+    pub(crate) fn with_tee(mut self, tee: &'a mut dyn Write, format: Format) -> Self {
+        self.tee = Some((tee, format));
+        self
+    }
+
+    /// Hand every line to `rows` instead of formatting it for the writer.
+    pub(crate) fn with_rows(mut self, rows: &'a mut dyn LineSink) -> Self {
+        self.rows = Some(rows);
+        self
+    }
+
+    /// Set the modification time of the inner file whose lines come next.
+    pub(crate) fn set_mtime(&mut self, mtime: Option<NaiveDateTime>) {
+        self.mtime = mtime.map(|mtime| mtime.format(MTIME_FORMAT).to_string());
+    }
+
+    /// Write the sidecar row of an output line from line `number` of `file`, if there is a
+    /// sidecar; `None` for separator lines.
+    fn write_sidecar(&mut self, file: &str, number: Option<u64>) -> Result<()> {
+        if let Some(sidecar) = &mut self.sidecar {
+            let mtime = self.mtime.as_deref().unwrap_or("");
+            match number {
+                Some(number) => writeln!(sidecar, "{file}\t{number}\t{mtime}")?,
+                None => writeln!(sidecar, "{file}\t\t{mtime}")?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `line` is suppressed by the configured [`Dedup`] mode. Remembers `line` otherwise.
+    fn is_duplicate(&mut self, line: &str) -> Result<bool> {
+        Ok(match self.options.dedup {
+            Dedup::Off => false,
+            Dedup::Consecutive => {
+                if self.last_line.as_deref() == Some(line) {
+                    return Ok(true);
+                }
+                self.last_line = Some(line.to_string());
+                false
+            }
+            Dedup::Global => !self.seen.insert(line)?,
+        })
+    }
+
+    /// Whether `line` falls within `--since` / `--until`. Lines without a parseable timestamp
+    /// pass unless `--strict-time` is set.
+    fn in_time_window(&self, line: &str) -> bool {
+        let (since, until) = (self.options.since, self.options.until);
+        if since.is_none() && until.is_none() {
+            return true;
+        }
+        match self.timestamps.and_then(|format| format.parse(line)) {
+            Some(time) => {
+                since.is_none_or(|since| time >= since) && until.is_none_or(|until| time < until)
+            }
+            None => !self.options.strict_time,
+        }
+    }
+
+    /// Apply the per-line transforms and filters of [`Options`] to `line`, counting what they
+    /// changed. Returns `None` if the line is to be dropped.
+    fn prepare<'l>(&mut self, line: &'l str) -> Result<Option<Cow<'l, str>>> {
+        let mut line = match &self.ansi {
+            Some(ansi) => ansi.replace_all(line, ""),
+            None => Cow::Borrowed(line),
+        };
+        if self.options.flatten_json_logs
+            && let Some(compact) = compact_json(&line)
+        {
+            line = Cow::Owned(compact);
+        }
+        if !self.in_time_window(&line) {
+            self.stats.out_of_time += 1;
+            return Ok(None);
+        }
+        if self.options.normalize_timestamps {
+            match self.timestamps.and_then(|format| format.normalize(&line)) {
+                Some(normalized) => line = Cow::Owned(normalized),
+                None if self.options.strict_time => {
+                    self.stats.out_of_time += 1;
+                    return Ok(None);
+                }
+                None => {}
+            }
+        }
+        if (!self.options.grep.is_empty() && !self.options.grep.is_match(&line))
+            || self.options.grep_invert.is_match(&line)
+        {
+            self.stats.grep_filtered += 1;
+            return Ok(None);
+        }
+        // Whitespace-only lines become empty but are still emitted.
+        if self.options.trim_trailing {
+            let len = line.trim_end().len();
+            match &mut line {
+                Cow::Borrowed(line) => *line = &line[..len],
+                Cow::Owned(line) => line.truncate(len),
+            }
+        }
+        if self.options.drop_empty && line.is_empty() {
+            self.stats.empty_lines += 1;
+            return Ok(None);
+        }
+        if let Some(replacement) = &self.options.replace_tabs
+            && line.contains('\t')
+        {
+            line = Cow::Owned(line.replace('\t', replacement));
+        }
+        if let Some(max) = self.options.max_line_bytes
+            && line.len() > max
+        {
+            self.stats.long_lines += 1;
+            match self.options.on_long_line {
+                LongLine::Drop => return Ok(None),
+                LongLine::Truncate => line = Cow::Owned(truncate(&line, max)),
+            }
+        }
+        if self.is_duplicate(&line)? {
+            self.stats.duplicates += 1;
+            return Ok(None);
+        }
+        if let Some((p, rng)) = &mut self.sampler {
+            self.stats.sampled += 1;
+            if !rng.random_bool(*p) {
+                self.stats.sampled_out += 1;
+                return Ok(None);
+            }
+        }
+        Ok(Some(line))
+    }
+
+    /// Whether `--head` has been reached or the run was stopped, so the rest of the archive can
+    /// be skipped.
+    pub(crate) fn is_done(&self) -> bool {
+        self.options
+            .head
+            .is_some_and(|head| self.stats.lines >= head)
+            || self.options.stopped()
+    }
+
+    /// Emit a single log `line`, line `number` (from 1) of the inner file `file`.
+    pub(crate) fn write_line(&mut self, file: &str, number: u64, line: &str) -> Result<()> {
+        if self.is_done() {
+            return Ok(());
+        }
+        let Some(line) = self.prepare(line)? else {
+            return Ok(());
+        };
+        let line = &*line;
+        if self.rows.is_some() && !spend(self.options, line.len()) {
+            return Ok(());
+        }
+        if let Some(rows) = &mut self.rows {
+            rows.write_row(self.dataset, file, number, line)?;
+            self.stats.lines += 1;
+            self.stats.bytes += line.len() as u64;
+            return Ok(());
+        }
+        // Numbered before `--tail` / `--sort` reorder the records, so numbers keep pointing
+        // into the archive.
+        let n = self.options.line_numbers.map(|_| {
+            self.line_number += 1;
+            self.line_number
+        });
+        let fields = self.options.parse.then(|| {
+            let fields = self.parser.as_ref().and_then(|parser| parser.parse(line));
+            fields.unwrap_or_else(|| {
+                self.stats.unparsed += 1;
+                Fields {
+                    content: line,
+                    ..Fields::default()
+                }
+            })
+        });
+        let mut buf = mem::take(&mut self.buf);
+        self.format_record(
+            self.options.format,
+            &mut buf,
+            n,
+            file,
+            line,
+            fields.as_ref(),
+        )?;
+        self.buf = buf;
+        if let Some(sorter) = &mut self.sorter {
+            return sorter.push(&self.buf);
+        }
+        if let Some(tail) = self.options.tail {
+            if tail == 0 {
+                return Ok(());
+            }
+            // Recycle the evicted record's allocation.
+            let mut record = if self.tail.len() == tail {
+                self.tail.pop_front().unwrap_or_default()
+            } else {
+                Vec::new()
+            };
+            record.clear();
+            record.extend_from_slice(&self.buf);
+            self.tail.push_back(record);
+            return Ok(());
+        }
+        let mut tee_buf = mem::take(&mut self.tee_buf);
+        tee_buf.clear();
+        if let Some(format) = self.tee_format() {
+            self.format_record(format, &mut tee_buf, n, file, line, fields.as_ref())?;
+        }
+        self.tee_buf = tee_buf;
+        if !spend(self.options, self.buf.len() + self.tee_buf.len()) {
+            return Ok(());
+        }
+        self.writer.write_all(&self.buf)?;
+        if let Some((tee, _)) = &mut self.tee {
+            tee.write_all(&self.tee_buf)?;
+        }
+        self.write_sidecar(file, Some(number))?;
+        self.stats.lines += 1;
+        self.stats.bytes += self.buf.len() as u64;
+        Ok(())
+    }
+
+    /// Format line `number` of `file` as a record in `format`, replacing the contents of `buf`.
+    fn format_record(
+        &self,
+        format: Format,
+        buf: &mut Vec<u8>,
+        n: Option<u64>,
+        file: &str,
+        line: &str,
+        fields: Option<&Fields>,
+    ) -> Result<()> {
+        buf.clear();
+        match format {
+            Format::Text => {
+                if let Some(n) = n {
+                    write!(buf, "{n}\t")?;
+                }
+                if self.options.annotate {
+                    let mtime = self.mtime.as_deref().unwrap_or("");
+                    write!(buf, "{}\t{}\t{}\t", self.dataset, file, mtime)?;
+                }
+                match fields {
+                    Some(fields) => write!(
+                        buf,
+                        "{}\t{}\t{}\t{}",
+                        fields.timestamp.unwrap_or(""),
+                        fields.level.unwrap_or(""),
+                        fields.component.unwrap_or(""),
+                        fields.content
+                    )?,
+                    None => buf.extend_from_slice(line.as_bytes()),
+                }
+            }
+            Format::Ndjson => {
+                let record = match fields {
+                    Some(fields) => Record {
+                        n,
+                        dataset: self.dataset,
+                        file,
+                        mtime: self.mtime.as_deref(),
+                        line: None,
+                        timestamp: fields.timestamp,
+                        level: fields.level,
+                        component: fields.component,
+                        content: Some(fields.content),
+                    },
+                    None => Record {
+                        n,
+                        dataset: self.dataset,
+                        file,
+                        mtime: self.mtime.as_deref(),
+                        line: Some(line),
+                        timestamp: None,
+                        level: None,
+                        component: None,
+                        content: None,
+                    },
+                };
+                serde_json::to_writer(&mut *buf, &record)?;
+            }
+            Format::Csv => {
+                let n = n.map(|n| n.to_string());
+                let mut row = n
+                    .as_deref()
+                    .into_iter()
+                    .chain([self.dataset, file, self.mtime.as_deref().unwrap_or("")])
+                    .collect::<Vec<_>>();
+                match fields {
+                    Some(fields) => row.extend([
+                        fields.timestamp.unwrap_or(""),
+                        fields.level.unwrap_or(""),
+                        fields.component.unwrap_or(""),
+                        fields.content,
+                    ]),
+                    None => row.push(line),
+                }
+                write_csv_row(&self.csv, buf, row)?;
+            }
+        }
+        buf.push(self.options.terminator()); // Ensure each log entry is on a new line
+        Ok(())
+    }
+
+    fn tee_format(&self) -> Option<Format> {
+        self.tee.as_ref().map(|&(_, format)| format)
+    }
+
+    /// Whether file boundaries are marked at all in output of `format`: only in text output,
+    /// and not once lines are reordered by `--tail` / `--sort` or cut off by `--head`.
+    fn marks_files(&self, format: Format) -> bool {
+        format == Format::Text
+            && self.rows.is_none()
+            && self.options.tail.is_none()
+            && self.sorter.is_none()
+            && !self.is_done()
+    }
+
+    /// Mark the start of the inner file `name` with [`Options::file_separator`], if set, and
+    /// restart per-file line numbers.
+    pub(crate) fn start_file(&mut self, name: &str) -> Result<()> {
+        if self.csv_header {
+            self.csv_header = false;
+            self.write_csv_header(name)?;
+        }
+        if self.options.line_numbers == Some(LineNumbers::PerFile) {
+            self.line_number = 0;
+        }
+        if let Some(separator) = &self.options.file_separator {
+            let mut line = separator.replace("{name}", name).into_bytes();
+            line.push(self.options.terminator());
+            self.write_marker(name, &line, Self::marks_files)?;
+        }
+        Ok(())
+    }
+
+    /// Write `line`, which is not a log line, at the inner file `name` to the outputs whose
+    /// format `wanted` accepts.
+    fn write_marker(
+        &mut self,
+        name: &str,
+        line: &[u8],
+        wanted: impl Fn(&Self, Format) -> bool,
+    ) -> Result<()> {
+        let primary = wanted(self, self.options.format);
+        let tee = self.tee_format().is_some_and(|format| wanted(self, format));
+        if !spend(self.options, line.len() * (primary as usize + tee as usize)) {
+            return Ok(());
+        }
+        if primary {
+            self.writer.write_all(line)?;
+            self.write_sidecar(name, None)?;
+            self.stats.bytes += line.len() as u64;
+        }
+        if tee && let Some((writer, _)) = &mut self.tee {
+            writer.write_all(line)?;
+        }
+        Ok(())
+    }
+
+    /// Write the column names of [`Format::Csv`] to the outputs in that format, before the first
+    /// inner file `name`.
+    fn write_csv_header(&mut self, name: &str) -> Result<()> {
+        let n = self.options.line_numbers.map(|_| "n");
+        let mut columns: Vec<&str> = n.into_iter().chain(["dataset", "file", "mtime"]).collect();
+        if self.options.parse {
+            columns.extend(["timestamp", "level", "component", "content"]);
+        } else {
+            columns.push("line");
+        }
+        let mut row = Vec::new();
+        write_csv_row(&self.csv, &mut row, columns)?;
+        row.push(self.options.terminator());
+        self.write_marker(name, &row, |_, format| format == Format::Csv)
+    }
+
+    /// Count the inner file whose lines come next; `true` if it has to be skipped because the
+    /// output resumed from already holds it.
+    pub(crate) fn skips_file(&mut self) -> bool {
+        self.files_seen += 1;
+        self.files_seen <= self.options.resume_from.files
+    }
+
+    /// Save the [`Options::checkpoint`] once an inner file (with its separator) was written
+    /// completely, which it wasn't if the archive ended early.
+    pub(crate) fn file_done(&mut self) -> Result<()> {
+        let Some(path) = &self.options.checkpoint else {
+            return Ok(());
+        };
+        if self.is_done() {
+            return Ok(());
+        }
+        self.writer.flush()?;
+        if let Some((tee, _)) = &mut self.tee {
+            tee.flush()?;
+        }
+        let resumed = self.options.resume_from;
+        Checkpoint {
+            files: self.files_seen,
+            lines: resumed.lines + self.stats.lines,
+            bytes: resumed.bytes + self.stats.bytes,
+        }
+        .save(path)
+    }
+
+    /// Mark the end of an inner file. Without a [`Options::file_separator`], text output
+    /// separates files with an empty line, unless empty lines are dropped or
+    /// [`Options::no_separator_newline`] is set; NDJSON has no separator so every line stays a
+    /// valid record.
+    pub(crate) fn end_file(&mut self, name: &str) -> Result<()> {
+        if self.options.file_separator.is_some()
+            || self.options.drop_empty
+            || self.options.no_separator_newline
+        {
+            return Ok(());
+        }
+        let separator = [self.options.terminator()];
+        self.write_marker(name, &separator, Self::marks_files)
+    }
+
+    /// Flush the lines held back for `--tail` or `--sort` and return the counts of the whole
+    /// archive. Invalid UTF-8 lines are only logged one by one at debug level, so they are
+    /// summed up here.
+    pub(crate) fn finish(mut self) -> Result<Stats> {
+        let (skipped, replaced) = (self.stats.skipped_non_utf8, self.stats.replaced_non_utf8);
+        if skipped > 0 {
+            log::warn!("{}: skipped {} invalid UTF-8 lines", self.dataset, skipped);
+        }
+        if replaced > 0 {
+            log::warn!(
+                "{}: replaced {} invalid UTF-8 lines",
+                self.dataset,
+                replaced
+            );
+        }
+        let Self {
+            writer,
+            options,
+            stats,
+            tail,
+            sorter,
+            ..
+        } = &mut self;
+        let mut emit = |record: &[u8]| -> Result<()> {
+            if !spend(options, record.len()) {
+                return Ok(());
+            }
+            writer.write_all(record)?;
+            stats.lines += 1;
+            stats.bytes += record.len() as u64;
+            Ok(())
+        };
+        for record in tail.iter() {
+            emit(record)?;
+        }
+        if let Some(sorter) = sorter.take() {
+            let duplicates = sorter.finish(emit)?;
+            stats.duplicates += duplicates;
+        }
+        Ok(self.stats)
+    }
+}
@@ -0,0 +1,155 @@
+//! Header fields of Loghub log lines, for `--parse`.
+
+use regex::Regex;
+
+use crate::time::is_dataset;
+
+/// The parts of one log line; header fields the dataset doesn't have are `None`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Fields<'a> {
+    pub(crate) timestamp: Option<&'a str>,
+    pub(crate) level: Option<&'a str>,
+    pub(crate) component: Option<&'a str>,
+    /// The message after the header, or the whole line if it doesn't match the format.
+    pub(crate) content: &'a str,
+}
+
+/// Splits the lines of one dataset into [`Fields`].
+#[derive(Debug)]
+pub(crate) struct LineParser(Regex);
+
+impl LineParser {
+    /// Split `line`; `None` if it doesn't match the dataset's format.
+    pub(crate) fn parse<'l>(&self, line: &'l str) -> Option<Fields<'l>> {
+        let captures = self.0.captures(line)?;
+        let field = |name| captures.name(name).map(|m| m.as_str());
+        Some(Fields {
+            timestamp: field("timestamp"),
+            level: field("level"),
+            component: field("component"),
+            content: field("content").unwrap_or(""),
+        })
+    }
+}
+
+/// Line formats keyed by dataset name, as named capture groups `timestamp`, `level`,
+/// `component` and `content`. Modelled on the templates Loghub's structured CSVs were made with.
+const LINE_FORMATS: &[(&str, &str)] = &[
+    // 081109 203518 143 INFO dfs.DataNode$DataXceiver: Receiving block …
+    (
+        "HDFS",
+        r"^(?P<timestamp>\d{6} \d{6}) \d+ (?P<level>\w+) (?P<component>[^:]+): (?P<content>.*)$",
+    ),
+    // 17/06/09 20:10:40 INFO executor.CoarseGrainedExecutorBackend: Registered signal handlers …
+    (
+        "Spark",
+        r"^(?P<timestamp>\d{2}/\d{2}/\d{2} \d{2}:\d{2}:\d{2}) (?P<level>\w+) (?P<component>[^:]+): (?P<content>.*)$",
+    ),
+    // - 1117838570 2005.06.03 R02-M1-N0-C:J12-U11 2005-06-03-15.42.50.675872 R02-M1-N0-C:J12-U11 RAS KERNEL INFO …
+    (
+        "BGL",
+        r"^\S+ \d+ \S+ \S+ (?P<timestamp>\S+) \S+ \S+ (?P<component>\S+) (?P<level>\S+) (?P<content>.*)$",
+    ),
+    // - 1131566461 2005.11.09 dn228 Nov 9 12:01:01 dn228/dn228 crond(pam_unix)[2915]: session closed …
+    (
+        "Thunderbird",
+        r"^\S+ \d+ \S+ \S+ (?P<timestamp>\w{3} +\d+ \d{2}:\d{2}:\d{2}) \S+ (?P<component>[^:\[]+)(?:\[\d+\])?: (?P<content>.*)$",
+    ),
+    // 2015-10-18 18:01:47,978 INFO [main] org.apache.hadoop.mapreduce.v2.app.MRAppMaster: Created …
+    (
+        "Hadoop",
+        r"^(?P<timestamp>\S+ \S+) (?P<level>\w+) \[[^\]]*\] (?P<component>[^:]+): (?P<content>.*)$",
+    ),
+    // 2015-07-29 17:41:44,747 - INFO  [QuorumPeer[myid=1]/0:0:0:0:0:0:0:0:2181:FastLeaderElection@774] - …
+    (
+        "Zookeeper",
+        r"^(?P<timestamp>\S+ \S+) - (?P<level>\w+) +\[(?P<component>.*?)\] - (?P<content>.*)$",
+    ),
+    // 03-17 16:13:38.811  1702  2395 D WindowManager: printFreezingDisplayLogs …
+    (
+        "Android",
+        r"^(?P<timestamp>\d{2}-\d{2} \S+) +\d+ +\d+ (?P<level>\w) (?P<component>[^:]*): (?P<content>.*)$",
+    ),
+    // 2016-09-28 04:30:30, Info                  CBS    Loaded Servicing Stack …
+    (
+        "Windows",
+        r"^(?P<timestamp>\S+ \S+), (?P<level>\w+) +(?P<component>\S+) +(?P<content>.*)$",
+    ),
+    // Jun 14 15:16:01 combo sshd(pam_unix)[19939]: authentication failure; …
+    (
+        "Linux",
+        r"^(?P<timestamp>\w{3} +\d+ \d{2}:\d{2}:\d{2}) \S+ (?P<component>[^:\[]+)(?:\[\d+\])?: (?P<content>.*)$",
+    ),
+    // Dec 10 06:55:46 LabSZ sshd[24200]: reverse mapping checking getaddrinfo …
+    (
+        "OpenSSH",
+        r"^(?P<timestamp>\w{3} +\d+ \d{2}:\d{2}:\d{2}) (?P<component>\S+) sshd\[\d+\]: (?P<content>.*)$",
+    ),
+    // [Sun Dec 04 04:47:44 2005] [notice] workerEnv.init() ok /etc/httpd/conf/workers2.properties
+    (
+        "Apache",
+        r"^\[(?P<timestamp>[^\]]+)\] \[(?P<level>\w+)\] (?P<content>.*)$",
+    ),
+    // 20171223-22:15:29:606|Step_LSC|30002312|onStandStepChanged 3579
+    (
+        "HealthApp",
+        r"^(?P<timestamp>[^|]+)\|(?P<component>[^|]+)\|\d+\|(?P<content>.*)$",
+    ),
+];
+
+/// Line parser of the dataset `stem`, matching e.g. both `HDFS` and `HDFS_1`.
+pub(crate) fn line_parser(stem: &str) -> Option<LineParser> {
+    LINE_FORMATS
+        .iter()
+        .find(|(name, _)| is_dataset(stem, name))
+        .map(|(_, pattern)| LineParser(Regex::new(pattern).expect("line formats are valid")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_lines_are_split_into_fields() {
+        for (dataset, line, expected) in [
+            (
+                "HDFS_1",
+                "081109 203518 143 INFO dfs.DataNode$DataXceiver: Receiving block blk_-1608999687919862906",
+                Fields {
+                    timestamp: Some("081109 203518"),
+                    level: Some("INFO"),
+                    component: Some("dfs.DataNode$DataXceiver"),
+                    content: "Receiving block blk_-1608999687919862906",
+                },
+            ),
+            (
+                "Thunderbird",
+                "- 1131566461 2005.11.09 dn228 Nov 9 12:01:01 dn228/dn228 crond(pam_unix)[2915]: session closed for user root",
+                Fields {
+                    timestamp: Some("Nov 9 12:01:01"),
+                    level: None,
+                    component: Some("crond(pam_unix)"),
+                    content: "session closed for user root",
+                },
+            ),
+            (
+                "Zookeeper",
+                "2015-07-29 17:41:44,747 - INFO  [QuorumPeer[myid=1]/0:0:0:0:0:0:0:0:2181:FastLeaderElection@774] - Notification time out: 3200",
+                Fields {
+                    timestamp: Some("2015-07-29 17:41:44,747"),
+                    level: Some("INFO"),
+                    component: Some(
+                        "QuorumPeer[myid=1]/0:0:0:0:0:0:0:0:2181:FastLeaderElection@774",
+                    ),
+                    content: "Notification time out: 3200",
+                },
+            ),
+        ] {
+            let parser = line_parser(dataset).unwrap();
+            assert_eq!(parser.parse(line), Some(expected), "{dataset}");
+        }
+        for (dataset, _) in LINE_FORMATS {
+            assert!(line_parser(dataset).unwrap().parse("garbage").is_none());
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Decompressing on a worker thread while the calling thread splits and writes lines.
+
+use std::{
+    io::{self, Read},
+    thread,
+};
+
+use anyhow::Result;
+use crossbeam_channel::{Receiver, bounded};
+
+/// Bytes of decompressed data per message.
+const CHUNK_SIZE: u64 = 256 * 1024;
+/// Chunks buffered between the threads before the decoder has to wait.
+const CHANNEL_CAPACITY: usize = 16;
+
+/// Read the stream returned by `open` on a worker thread and hand it to `consume` on this one.
+/// The stream is created on the worker, so it need not be [`Send`] itself; when `consume`
+/// returns early, the worker stops at its next chunk.
+pub(crate) fn pipelined<'a, T>(
+    open: impl FnOnce() -> Box<dyn Read + 'a> + Send + 'a,
+    consume: impl FnOnce(ChannelReader) -> Result<T>,
+) -> Result<T> {
+    let (sender, receiver) = bounded(CHANNEL_CAPACITY);
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let mut reader = open();
+            loop {
+                let mut chunk = Vec::new();
+                let message = match (&mut reader).take(CHUNK_SIZE).read_to_end(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(_) => Ok(chunk),
+                    Err(e) => Err(e),
+                };
+                let failed = message.is_err();
+                // A closed channel means the consumer is done.
+                if sender.send(message).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        consume(ChannelReader {
+            receiver,
+            chunk: Vec::new(),
+            pos: 0,
+        })
+    })
+}
+
+/// The consuming end of [`pipelined`].
+pub(crate) struct ChannelReader {
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                // The worker hung up: end of stream.
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,125 @@
+//! Sorting the records of one archive for `--sort`, spilling to temporary files when they don't
+//! fit in memory.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Seek, Write},
+    mem,
+};
+
+use anyhow::Result;
+
+/// Ordering of the records of an archive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Sort {
+    /// Keep the order of the archive.
+    #[default]
+    Off,
+    /// Sort records bytewise.
+    Lines,
+    /// Sort records bytewise and keep one of each distinct record.
+    Unique,
+}
+
+/// Collects terminated records and hands them back in sorted order.
+pub(crate) struct Sorter {
+    unique: bool,
+    /// Last byte of every record, `\n` unless NUL-separated.
+    terminator: u8,
+    /// Buffered bytes after which `records` is sorted and spilled into a run. `None` never spills.
+    max_memory: Option<usize>,
+    records: Vec<Vec<u8>>,
+    /// Approximate heap usage of `records`.
+    memory: usize,
+    /// Sorted temporary files of terminated records.
+    runs: Vec<File>,
+}
+
+impl Sorter {
+    pub(crate) fn new(unique: bool, max_memory: Option<usize>, terminator: u8) -> Self {
+        Self {
+            unique,
+            terminator,
+            max_memory,
+            records: Vec::new(),
+            memory: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Buffer one terminated `record`.
+    pub(crate) fn push(&mut self, record: &[u8]) -> Result<()> {
+        self.memory += record.len() + mem::size_of::<Vec<u8>>();
+        self.records.push(record.to_vec());
+        if self.max_memory.is_some_and(|max| self.memory > max) {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Sort the buffered records into a new run on disk.
+    fn spill(&mut self) -> Result<()> {
+        self.records.sort_unstable();
+        let mut run = BufWriter::new(tempfile::tempfile()?);
+        for record in self.records.drain(..) {
+            run.write_all(&record)?;
+        }
+        let mut run = run.into_inner().map_err(|e| e.into_error())?;
+        run.rewind()?;
+        self.runs.push(run);
+        self.memory = 0;
+        Ok(())
+    }
+
+    /// Pass every record to `emit` in sorted order. Returns how many duplicates were left out.
+    pub(crate) fn finish(mut self, mut emit: impl FnMut(&[u8]) -> Result<()>) -> Result<u64> {
+        // Once anything went to disk, merge the runs, the last one being whatever is still
+        // buffered.
+        if !self.runs.is_empty() && !self.records.is_empty() {
+            self.spill()?;
+        }
+        let unique = self.unique;
+        let mut duplicates = 0;
+        let mut last: Option<Vec<u8>> = None;
+        let mut emit_unique = |record: Vec<u8>| -> Result<()> {
+            if unique && last.as_ref() == Some(&record) {
+                duplicates += 1;
+                return Ok(());
+            }
+            emit(&record)?;
+            last = Some(record);
+            Ok(())
+        };
+
+        if self.runs.is_empty() {
+            self.records.sort_unstable();
+            for record in self.records {
+                emit_unique(record)?;
+            }
+            return Ok(duplicates);
+        }
+
+        let mut readers: Vec<BufReader<File>> = self.runs.into_iter().map(BufReader::new).collect();
+        let mut heap = BinaryHeap::new();
+        for (i, reader) in readers.iter_mut().enumerate() {
+            if let Some(record) = read_record(reader, self.terminator)? {
+                heap.push(Reverse((record, i)));
+            }
+        }
+        while let Some(Reverse((record, i))) = heap.pop() {
+            if let Some(next) = read_record(&mut readers[i], self.terminator)? {
+                heap.push(Reverse((next, i)));
+            }
+            emit_unique(record)?;
+        }
+        Ok(duplicates)
+    }
+}
+
+/// Read the next record of a run, up to and including `terminator`, or `None` at its end.
+fn read_record(reader: &mut impl BufRead, terminator: u8) -> Result<Option<Vec<u8>>> {
+    let mut record = Vec::new();
+    Ok((reader.read_until(terminator, &mut record)? > 0).then_some(record))
+}
@@ -0,0 +1,70 @@
+//! A [`LineSink`] inserting lines into a SQLite database, for `--sqlite`.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rusqlite::Connection;
+
+use crate::LineSink;
+
+const INSERT: &str = "INSERT INTO logs (dataset, file, line_no, content) VALUES (?1, ?2, ?3, ?4)";
+
+/// Appends every line to the table `logs(dataset, file, line_no, content)`, creating it if
+/// needed. Rows are inserted in a transaction (committing every row would make inserting slower
+/// by orders of magnitude) that [`SqliteSink::commit`] or [`SqliteSink::rollback`] ends, so
+/// each archive can be kept or dropped as a whole.
+pub struct SqliteSink {
+    conn: Connection,
+    /// Whether rows were inserted since the last commit or rollback.
+    pending: bool,
+}
+
+impl SqliteSink {
+    /// Open or create the database at `path`.
+    pub fn create(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path).with_context(|| format!("opening {}", path.display()))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS logs \
+             (dataset TEXT, file TEXT, line_no INTEGER, content TEXT)",
+        )
+        .with_context(|| format!("creating the logs table in {}", path.display()))?;
+        Ok(Self {
+            conn,
+            pending: false,
+        })
+    }
+
+    /// Commit the rows inserted since the last commit or rollback.
+    pub fn commit(&mut self) -> Result<()> {
+        if std::mem::take(&mut self.pending) {
+            self.conn.execute_batch("COMMIT")?;
+        }
+        Ok(())
+    }
+
+    /// Drop the rows inserted since the last commit or rollback.
+    pub fn rollback(&mut self) -> Result<()> {
+        if std::mem::take(&mut self.pending) {
+            self.conn.execute_batch("ROLLBACK")?;
+        }
+        Ok(())
+    }
+
+    /// Commit the rows inserted so far.
+    pub fn finish(mut self) -> Result<()> {
+        self.commit()
+    }
+}
+
+impl LineSink for SqliteSink {
+    fn write_row(&mut self, dataset: &str, file: &str, number: u64, line: &str) -> Result<()> {
+        if !self.pending {
+            self.conn.execute_batch("BEGIN")?;
+            self.pending = true;
+        }
+        self.conn
+            .prepare_cached(INSERT)?
+            .execute((dataset, file, number as i64, line))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! Counters reported after converting an archive.
+
+use std::{fmt, ops::AddAssign};
+
+use serde::Serialize;
+
+/// What a conversion produced. Summed across archives for the run total.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    /// Inner files that were streamed.
+    pub files: usize,
+    /// Log lines written.
+    pub lines: u64,
+    /// Bytes written, including separators and annotations.
+    pub bytes: u64,
+    /// Lines dropped because they were not valid UTF-8.
+    pub skipped_non_utf8: u64,
+    /// Lines that were not valid UTF-8 and written repaired or replaced (`--invalid-utf8`).
+    pub replaced_non_utf8: u64,
+    /// Lines suppressed by `--dedup` / `--dedup-global` / `--sort-unique`.
+    pub duplicates: u64,
+    /// Lines longer than `--max-line-bytes`, truncated or dropped.
+    pub long_lines: u64,
+    /// Empty lines left out by `--drop-empty`.
+    pub empty_lines: u64,
+    /// Lines outside `--since` / `--until`, or undated ones with `--strict-time`.
+    pub out_of_time: u64,
+    /// Lines not matching `--grep` or matching `--grep-invert`.
+    pub grep_filtered: u64,
+    /// Lines written as bare content because they didn't match the dataset's format (`--parse`).
+    pub unparsed: u64,
+    /// Zip entries whose CRC didn't match, written anyway (without `--strict-crc`).
+    pub crc_mismatches: u64,
+    /// Archive entries skipped for not being regular files or directories: links, devices, …
+    pub skipped_entries: u64,
+    /// Lines `--sample` chose from, after the other filters.
+    pub sampled: u64,
+    /// Of those, the lines it left out.
+    pub sampled_out: u64,
+    /// SHA-256 of the archive file, when requested via `Options::hash_input`. Only meaningful
+    /// for a single archive, so summing stats clears it.
+    #[serde(skip)]
+    pub input_sha256: Option<[u8; 32]>,
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, other: Stats) {
+        self.files += other.files;
+        self.lines += other.lines;
+        self.bytes += other.bytes;
+        self.skipped_non_utf8 += other.skipped_non_utf8;
+        self.replaced_non_utf8 += other.replaced_non_utf8;
+        self.duplicates += other.duplicates;
+        self.long_lines += other.long_lines;
+        self.empty_lines += other.empty_lines;
+        self.out_of_time += other.out_of_time;
+        self.grep_filtered += other.grep_filtered;
+        self.unparsed += other.unparsed;
+        self.crc_mismatches += other.crc_mismatches;
+        self.skipped_entries += other.skipped_entries;
+        self.sampled += other.sampled;
+        self.sampled_out += other.sampled_out;
+        self.input_sha256 = None;
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files, {} lines, {} bytes, {} invalid UTF-8 lines skipped",
+            self.files, self.lines, self.bytes, self.skipped_non_utf8
+        )?;
+        // Counters of opt-in features are only shown once they triggered.
+        if self.replaced_non_utf8 > 0 {
+            write!(
+                f,
+                ", {} invalid UTF-8 lines replaced",
+                self.replaced_non_utf8
+            )?;
+        }
+        if self.duplicates > 0 {
+            write!(f, ", {} duplicates suppressed", self.duplicates)?;
+        }
+        if self.long_lines > 0 {
+            write!(f, ", {} over-long lines", self.long_lines)?;
+        }
+        if self.empty_lines > 0 {
+            write!(f, ", {} empty lines dropped", self.empty_lines)?;
+        }
+        if self.out_of_time > 0 {
+            write!(f, ", {} lines outside the time window", self.out_of_time)?;
+        }
+        if self.grep_filtered > 0 {
+            write!(f, ", {} lines filtered by --grep", self.grep_filtered)?;
+        }
+        if self.unparsed > 0 {
+            write!(
+                f,
+                ", {} lines not matching the dataset's format",
+                self.unparsed
+            )?;
+        }
+        if self.crc_mismatches > 0 {
+            write!(
+                f,
+                ", {} entries failed their CRC check",
+                self.crc_mismatches
+            )?;
+        }
+        if self.skipped_entries > 0 {
+            write!(f, ", {} non-file entries skipped", self.skipped_entries)?;
+        }
+        if self.sampled > 0 {
+            write!(
+                f,
+                ", sample kept {} of {} lines",
+                self.sampled - self.sampled_out,
+                self.sampled
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+//! Leading timestamps of Loghub log lines, for `--since` / `--until` and
+//! `--normalize-timestamps`.
+
+use chrono::NaiveDateTime;
+
+/// How the lines of one dataset start with a timestamp.
+#[derive(Debug)]
+pub(crate) struct TimestampFormat {
+    /// Byte length of the timestamp at the start of each line.
+    len: usize,
+    /// `chrono` format of those bytes.
+    format: &'static str,
+}
+
+impl TimestampFormat {
+    const fn new(len: usize, format: &'static str) -> Self {
+        Self { len, format }
+    }
+
+    /// Parse the timestamp at the start of `line`, if it has one.
+    pub(crate) fn parse(&self, line: &str) -> Option<NaiveDateTime> {
+        NaiveDateTime::parse_from_str(line.get(..self.len)?, self.format).ok()
+    }
+
+    /// `line` with its leading timestamp rewritten as ISO 8601. Milliseconds right after it
+    /// (`,978` for Hadoop, `:606` for HealthApp) become the fraction of the new timestamp.
+    pub(crate) fn normalize(&self, line: &str) -> Option<String> {
+        let time = self.parse(line)?;
+        let mut rest = &line[self.len..];
+        let mut normalized = time.format("%Y-%m-%dT%H:%M:%S").to_string();
+        if let [b',' | b'.' | b':', millis @ ..] = rest.as_bytes()
+            && millis.len() >= 3
+            && millis[..3].iter().all(u8::is_ascii_digit)
+            && !millis.get(3).is_some_and(u8::is_ascii_digit)
+        {
+            normalized.push('.');
+            normalized.push_str(&rest[1..4]);
+            rest = &rest[4..];
+        }
+        normalized.push_str(rest);
+        Some(normalized)
+    }
+}
+
+/// Timestamp formats keyed by dataset name. Datasets whose lines don't start with a full date
+/// (e.g. `Linux`'s `Jun 14 15:16:01` lacks the year) are not listed.
+const TIMESTAMP_FORMATS: &[(&str, TimestampFormat)] = &[
+    ("HDFS", TimestampFormat::new(13, "%y%m%d %H%M%S")), // 081109 203518
+    ("Spark", TimestampFormat::new(17, "%y/%m/%d %H:%M:%S")), // 17/06/09 20:10:40
+    ("Hadoop", TimestampFormat::new(19, "%Y-%m-%d %H:%M:%S")), // 2015-10-18 18:01:47,978
+    ("Zookeeper", TimestampFormat::new(19, "%Y-%m-%d %H:%M:%S")), // 2015-07-29 17:41:44,747
+    ("Windows", TimestampFormat::new(19, "%Y-%m-%d %H:%M:%S")), // 2016-09-28 04:30:30, Info
+    ("HealthApp", TimestampFormat::new(17, "%Y%m%d-%H:%M:%S")), // 20171223-22:15:29:606
+    ("Apache", TimestampFormat::new(26, "[%a %b %d %H:%M:%S %Y]")), // [Sun Dec 04 04:47:44 2005]
+];
+
+/// Timestamp format of the dataset `stem`, matching e.g. both `HDFS` and `HDFS_1`.
+pub(crate) fn timestamp_format(stem: &str) -> Option<&'static TimestampFormat> {
+    TIMESTAMP_FORMATS
+        .iter()
+        .find_map(|(name, format)| is_dataset(stem, name).then_some(format))
+}
+
+/// Whether the archive `stem` holds the dataset `name`, possibly one of several parts
+/// (`HDFS_1`).
+pub(crate) fn is_dataset(stem: &str, name: &str) -> bool {
+    stem.strip_prefix(name)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('_'))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn dataset_timestamps_are_parsed() {
+        let at = |y, m, d, h, min, s| {
+            NaiveDate::from_ymd_opt(y, m, d)
+                .unwrap()
+                .and_hms_opt(h, min, s)
+                .unwrap()
+        };
+        for (dataset, line, time) in [
+            (
+                "HDFS_1",
+                "081109 203518 143 INFO dfs.DataNode",
+                at(2008, 11, 9, 20, 35, 18),
+            ),
+            (
+                "Spark",
+                "17/06/09 20:10:40 INFO executor",
+                at(2017, 6, 9, 20, 10, 40),
+            ),
+            (
+                "Hadoop",
+                "2015-10-18 18:01:47,978 INFO",
+                at(2015, 10, 18, 18, 1, 47),
+            ),
+            (
+                "Zookeeper",
+                "2015-07-29 17:41:44,747 - INFO",
+                at(2015, 7, 29, 17, 41, 44),
+            ),
+            (
+                "Windows",
+                "2016-09-28 04:30:30, Info CBS",
+                at(2016, 9, 28, 4, 30, 30),
+            ),
+            (
+                "HealthApp",
+                "20171223-22:15:29:606|Step_LSC",
+                at(2017, 12, 23, 22, 15, 29),
+            ),
+            (
+                "Apache",
+                "[Sun Dec 04 04:47:44 2005] [notice]",
+                at(2005, 12, 4, 4, 47, 44),
+            ),
+        ] {
+            let format = timestamp_format(dataset).unwrap();
+            assert_eq!(format.parse(line), Some(time), "{dataset}");
+        }
+    }
+
+    #[test]
+    fn lines_without_a_timestamp_are_left_alone() {
+        let hdfs = timestamp_format("HDFS").unwrap();
+        for line in [
+            "",
+            "0811",
+            "at java.lang.Thread.run",
+            "081109 ab3518 143 INFO",
+        ] {
+            assert_eq!(hdfs.parse(line), None, "{line:?}");
+            assert_eq!(hdfs.normalize(line), None, "{line:?}");
+        }
+        assert!(timestamp_format("Linux").is_none());
+        assert!(timestamp_format("HDFSv2").is_none());
+    }
+
+    #[test]
+    fn normalized_timestamps_keep_their_milliseconds() {
+        let hadoop = timestamp_format("Hadoop").unwrap();
+        assert_eq!(
+            hadoop.normalize("2015-10-18 18:01:47,978 INFO").unwrap(),
+            "2015-10-18T18:01:47.978 INFO"
+        );
+        let health = timestamp_format("HealthApp").unwrap();
+        assert_eq!(
+            health.normalize("20171223-22:15:29:606|Step").unwrap(),
+            "2017-12-23T22:15:29.606|Step"
+        );
+        // Only exactly three digits are milliseconds.
+        let windows = timestamp_format("Windows").unwrap();
+        assert_eq!(
+            windows.normalize("2016-09-28 04:30:30, Info").unwrap(),
+            "2016-09-28T04:30:30, Info"
+        );
+        let zookeeper = timestamp_format("Zookeeper").unwrap();
+        assert_eq!(
+            zookeeper.normalize("2015-07-29 17:41:44:74712").unwrap(),
+            "2015-07-29T17:41:44:74712"
+        );
+    }
+}
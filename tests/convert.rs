@@ -0,0 +1,991 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use datasets::{
+    ArchiveKind, ByteBudget, Checkpoint, Converter, Dedup, Format, InvalidUtf8, LineNumbers,
+    LineSink, LogLines, MemoryGate, Options, Sort, SqliteSink, Stats, convert_archive_rows,
+    convert_archive_split, convert_archive_tee, convert_archive_with, convert_archive_with_sidecar,
+    dataset_stem, detect_archive_kind, estimate_memory, list_archive, stream_7z, stream_tar_gz,
+    stream_zip,
+};
+use flate2::{Compression, write::GzEncoder};
+use regex::{Regex, RegexSet};
+use tempfile::TempDir;
+use zip::write::FileOptions;
+
+/// Inner files of every fixture: nested directories, CRLF and a line that is not UTF-8.
+const ENTRIES: &[(&str, &[u8])] = &[
+    ("logs/a.log", b"a1\na2\n"),
+    ("logs/nested/b.log", b"b1\r\n\xff\xfe broken\r\nb3\r\n"),
+];
+
+fn tar_gz_fixture(dir: &TempDir, name: &str) -> PathBuf {
+    let path = dir.path().join(name);
+    let encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for &(entry, data) in ENTRIES {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, entry, data).unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap();
+    path
+}
+
+fn zip_fixture(dir: &TempDir, name: &str) -> PathBuf {
+    let path = dir.path().join(name);
+    let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+    writer
+        .add_directory("logs/", FileOptions::default())
+        .unwrap();
+    for &(entry, data) in ENTRIES {
+        writer.start_file(entry, FileOptions::default()).unwrap();
+        writer.write_all(data).unwrap();
+    }
+    writer.finish().unwrap();
+    path
+}
+
+/// All files go into one solid block, so skipping one means decoding past it.
+fn sevenz_fixture(dir: &TempDir, name: &str) -> PathBuf {
+    use sevenz_rust::{SevenZArchiveEntry, SevenZWriter, SourceReader};
+
+    let path = dir.path().join(name);
+    let mut writer = SevenZWriter::create(&path).unwrap();
+    let mut dir_entry = SevenZArchiveEntry::new();
+    dir_entry.name = "logs".to_string();
+    dir_entry.is_directory = true;
+    writer.push_archive_entry::<&[u8]>(dir_entry, None).unwrap();
+    let entries = ENTRIES
+        .iter()
+        .map(|&(name, _)| {
+            let mut entry = SevenZArchiveEntry::new();
+            entry.name = name.to_string();
+            // Not set by `push_archive_entries` itself.
+            entry.has_stream = true;
+            entry
+        })
+        .collect();
+    let readers: Vec<_> = ENTRIES
+        .iter()
+        .map(|&(_, data)| SourceReader::new(data))
+        .collect();
+    writer
+        .push_archive_entries(entries, readers.into())
+        .unwrap();
+    writer.finish().unwrap();
+    path
+}
+
+fn convert(
+    stream: fn(&Path, &mut Vec<u8>, &Options) -> anyhow::Result<Stats>,
+    path: &Path,
+) -> (String, Stats) {
+    let mut out = Vec::new();
+    let stats = stream(path, &mut out, &Options::default()).unwrap();
+    (String::from_utf8(out).unwrap(), stats)
+}
+
+#[test]
+fn tar_gz_flattens_entries_and_skips_invalid_utf8() {
+    let dir = tempfile::tempdir().unwrap();
+    let (out, stats) = convert(stream_tar_gz, &tar_gz_fixture(&dir, "Spark.tar.gz"));
+    assert_eq!(out, "a1\na2\nb1\nb3\n");
+    assert_eq!(stats.files, 2);
+    assert_eq!(stats.lines, 4);
+    assert_eq!(stats.skipped_non_utf8, 1);
+}
+
+#[test]
+fn zip_flattens_entries_and_skips_invalid_utf8() {
+    let dir = tempfile::tempdir().unwrap();
+    let (out, stats) = convert(stream_zip, &zip_fixture(&dir, "Android.zip"));
+    // Zip entries are separated by an empty line; the directory entry is not a file.
+    assert_eq!(out, "a1\na2\n\nb1\nb3\n\n");
+    assert_eq!(stats.files, 2);
+    assert_eq!(stats.lines, 4);
+    assert_eq!(stats.skipped_non_utf8, 1);
+}
+
+#[test]
+fn sevenz_entries_are_separated_like_zip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = sevenz_fixture(&dir, "Mac.7z");
+    assert_eq!(dataset_stem(&path), "Mac");
+    let (out, stats) = convert(stream_7z, &path);
+    assert_eq!(out, "a1\na2\n\nb1\nb3\n\n");
+    assert_eq!(stats.files, 2);
+    assert_eq!(stats.skipped_non_utf8, 1);
+
+    let options = Options {
+        min_file_size: Some(7),
+        ..Options::default()
+    };
+    let mut out = Vec::new();
+    convert_archive_with(&path, &mut out, &options).unwrap();
+    assert_eq!(out, b"b1\nb3\n\n");
+}
+
+#[test]
+fn zip_crc_mismatches_are_counted_or_fail_with_strict_crc() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Android.zip");
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let stored = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    writer.start_file("a.log", stored).unwrap();
+    writer.write_all(b"corrupt\n").unwrap();
+    let mut bytes = writer.finish().unwrap().into_inner();
+    let at = bytes.windows(7).position(|w| w == b"corrupt").unwrap();
+    bytes[at] = b'C';
+    std::fs::write(&path, bytes).unwrap();
+
+    let (out, stats) = convert(stream_zip, &path);
+    assert_eq!(out, "Corrupt\n\n");
+    assert_eq!(stats.crc_mismatches, 1);
+
+    let options = Options {
+        strict_crc: true,
+        ..Options::default()
+    };
+    let mut out = Vec::new();
+    assert!(stream_zip(&path, &mut out, &options).is_err());
+    assert!(out.is_empty());
+}
+
+#[test]
+fn zip_symlinks_are_skipped_and_counted() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Android.zip");
+    let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+    writer.start_file("a.log", FileOptions::default()).unwrap();
+    writer.write_all(b"one\n").unwrap();
+    writer
+        .add_symlink("link.log", "a.log", FileOptions::default())
+        .unwrap();
+    writer.finish().unwrap();
+
+    let (out, stats) = convert(stream_zip, &path);
+    assert_eq!(out, "one\n\n");
+    assert_eq!(stats.files, 1);
+    assert_eq!(stats.skipped_entries, 1);
+}
+
+#[test]
+fn resumed_output_continues_from_its_checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = zip_fixture(&dir, "Android.zip");
+    let checkpoint = dir.path().join("Android_logs.txt.checkpoint");
+    let options = Options {
+        line_numbers: Some(LineNumbers::Global),
+        checkpoint: Some(checkpoint.clone()),
+        ..Options::default()
+    };
+    let mut full = Vec::new();
+    stream_zip(&path, &mut full, &options).unwrap();
+    let first_file = b"1\ta1\n2\ta2\n\n";
+    assert!(full.starts_with(first_file));
+    let done = Checkpoint {
+        files: 2,
+        lines: 4,
+        bytes: full.len() as u64,
+    };
+    assert_eq!(Checkpoint::load(&checkpoint).unwrap(), Some(done));
+
+    let mut rest = Vec::new();
+    let options = Options {
+        resume_from: Checkpoint {
+            files: 1,
+            lines: 2,
+            bytes: first_file.len() as u64,
+        },
+        ..options
+    };
+    let stats = stream_zip(&path, &mut rest, &options).unwrap();
+    assert_eq!(rest, &full[first_file.len()..]);
+    assert_eq!(stats.files, 1);
+    assert_eq!(Checkpoint::load(&checkpoint).unwrap(), Some(done));
+}
+
+#[test]
+fn byte_budget_is_shared_by_archives() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = tar_gz_fixture(&dir, "Spark.tar.gz");
+    let budget = Arc::new(ByteBudget::new(7));
+    let options = Options {
+        byte_budget: Some(Arc::clone(&budget)),
+        ..Options::default()
+    };
+    let mut out = Vec::new();
+    stream_tar_gz(&path, &mut out, &options).unwrap();
+    // `b1\n` would make it 9 bytes.
+    assert_eq!(out, b"a1\na2\n");
+    assert!(budget.is_exhausted() && options.stopped());
+    let mut out = Vec::new();
+    stream_tar_gz(&path, &mut out, &options).unwrap();
+    assert!(out.is_empty());
+    assert_eq!(budget.used(), 6);
+}
+
+#[test]
+fn sorting_merges_the_runs_spilled_past_max_memory() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Android.zip");
+    let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+    let lines: Vec<String> = (0..300).map(|i| format!("line {}", i * 37 % 100)).collect();
+    for (entry, chunk) in ["a.log", "b.log", "c.log"].iter().zip(lines.chunks(100)) {
+        writer.start_file(*entry, FileOptions::default()).unwrap();
+        for line in chunk {
+            writeln!(writer, "{line}").unwrap();
+        }
+    }
+    writer.finish().unwrap();
+    let mut sorted = lines.clone();
+    sorted.sort();
+
+    for (sort, expected) in [
+        (Sort::Lines, sorted.clone()),
+        (Sort::Unique, {
+            let mut unique = sorted.clone();
+            unique.dedup();
+            unique
+        }),
+    ] {
+        let options = Options {
+            sort,
+            // A few records per run, so dozens of runs are merged.
+            max_memory: Some(128),
+            ..Options::default()
+        };
+        let mut out = Vec::new();
+        let stats = convert_archive_with(&path, &mut out, &options).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let written: Vec<&str> = out.lines().filter(|line| !line.is_empty()).collect();
+        assert_eq!(written, expected, "{sort:?}");
+        assert_eq!(stats.duplicates, (lines.len() - expected.len()) as u64);
+    }
+}
+
+#[test]
+fn since_and_until_keep_the_lines_in_between() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("HDFS_1.zip");
+    let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+    writer
+        .start_file("HDFS.log", FileOptions::default())
+        .unwrap();
+    writer
+        .write_all(
+            b"081109 203518 early\n081110 000000 first\n\tat a continuation\n\
+              081110 235959 last\n081111 000000 late\n",
+        )
+        .unwrap();
+    writer.finish().unwrap();
+    let midnight = |day| {
+        chrono::NaiveDate::from_ymd_opt(2008, 11, day)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+    };
+    let options = Options {
+        since: Some(midnight(10)),
+        until: Some(midnight(11)),
+        ..Options::default()
+    };
+    let mut out = Vec::new();
+    let stats = convert_archive_with(&path, &mut out, &options).unwrap();
+    // `since` is inclusive, `until` exclusive; the line without a timestamp is kept.
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "081110 000000 first\n\tat a continuation\n081110 235959 last\n\n"
+    );
+    assert_eq!(stats.out_of_time, 2);
+}
+
+#[test]
+fn split_outputs_stop_at_the_byte_budget() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Android.zip");
+    let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+    for (entry, data) in [("a.log", "a1\na2\n"), ("b.log", "b1\n"), ("c.log", "c1\n")] {
+        writer.start_file(entry, FileOptions::default()).unwrap();
+        writer.write_all(data.as_bytes()).unwrap();
+    }
+    writer.finish().unwrap();
+    let options = Options {
+        byte_budget: Some(Arc::new(ByteBudget::new(7))),
+        mmap: true,
+        ..Options::default()
+    };
+    let mut created = Vec::new();
+    convert_archive_split(
+        &path,
+        &options,
+        |name| {
+            created.push(name.to_string());
+            Ok(Vec::new())
+        },
+        |_| Ok(()),
+    )
+    .unwrap();
+    // `b1\n` doesn't fit, and nothing is opened for `c.log` after that.
+    assert_eq!(created, ["a.log", "b.log"]);
+}
+
+#[test]
+fn memory_gate_charges_zip_central_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = Options::default();
+    let tar = estimate_memory(&tar_gz_fixture(&dir, "Spark.tar.gz"), &options).unwrap();
+    let zip = estimate_memory(&zip_fixture(&dir, "Android.zip"), &options).unwrap();
+    assert!(zip > tar, "{zip} <= {tar}");
+
+    // An archive over the whole budget still gets to run, once nothing else holds any of it.
+    let gate = MemoryGate::new(zip / 2);
+    drop(gate.acquire(zip));
+    let _first = gate.acquire(zip / 4);
+    let _second = gate.acquire(zip / 4);
+}
+
+#[test]
+fn bytes_read_add_up_to_the_archive_sizes() {
+    let dir = tempfile::tempdir().unwrap();
+    let read = Arc::new(AtomicU64::new(0));
+    let options = Options {
+        bytes_read: Some(Arc::clone(&read)),
+        ..Options::default()
+    };
+    let mut total = 0;
+    // The zip is read with seeks, and `head` leaves most of the tar unread.
+    for (path, head) in [
+        (zip_fixture(&dir, "Android.zip"), None),
+        (tar_gz_fixture(&dir, "Spark.tar.gz"), Some(1)),
+    ] {
+        let options = Options {
+            head,
+            ..options.clone()
+        };
+        convert_archive_with(&path, &mut Vec::new(), &options).unwrap();
+        total += std::fs::metadata(&path).unwrap().len();
+        assert_eq!(read.load(Ordering::Relaxed), total, "{}", path.display());
+    }
+}
+
+#[test]
+fn failures_still_exit_partial_when_the_byte_cap_is_hit() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("Bad.tar.gz"), b"\x1f\x8bnot gzip at all").unwrap();
+    tar_gz_fixture(&dir, "Spark.tar.gz");
+    let out = dir.path().join("out");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_datasets"))
+        .arg("--input-dir")
+        .arg(dir.path())
+        .arg("--output-dir")
+        .arg(&out)
+        .args(["--max-total-bytes", "4", "--jobs", "1", "--quiet"])
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(2));
+    assert_eq!(std::fs::read(out.join("Spark_logs.txt")).unwrap(), b"a1\n");
+}
+
+#[test]
+fn config_values_obey_the_flag_checks() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = tar_gz_fixture(&dir, "Spark.tar.gz");
+    let config = dir.path().join("loghub.toml");
+    std::fs::write(&config, "buffer-size = 0\n").unwrap();
+    let out = dir.path().join("out");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_datasets"))
+        .arg(&path)
+        .arg("--config")
+        .arg(&config)
+        .arg("--output-dir")
+        .arg(&out)
+        .arg("--quiet")
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    assert!(!out.exists());
+
+    // `--stdout` conflicts with `--compress`, so the config's goes unused.
+    std::fs::write(&config, "compress = true\n").unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_datasets"))
+        .arg(&path)
+        .arg("--config")
+        .arg(&config)
+        .args(["--stdout", "--quiet"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"a1\na2\nb1\nb3\n");
+}
+
+#[test]
+fn cache_tells_conversion_flags_apart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = tar_gz_fixture(&dir, "Spark.tar.gz");
+    let out = dir.path().join("out");
+    let summary = dir.path().join("summary.json");
+    let run = |flags: &[&str]| {
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_datasets"))
+            .arg(&path)
+            .arg("--output-dir")
+            .arg(&out)
+            .arg("--summary-json")
+            .arg(&summary)
+            .args(flags)
+            .arg("--quiet")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        serde_json::from_slice::<serde_json::Value>(&std::fs::read(&summary).unwrap()).unwrap()
+    };
+    run(&[]);
+    let converted = run(&["--grep", "a1"]);
+    assert_eq!(std::fs::read(out.join("Spark_logs.txt")).unwrap(), b"a1\n");
+    assert_eq!(converted["archives"][0]["lines"], 1);
+    let skipped = run(&["--grep", "a1"]);
+    assert_eq!(skipped["archives"][0]["skipped"], true);
+    assert!(skipped["archives"][0].get("lines").is_none());
+}
+
+#[test]
+fn compressed_outputs_are_not_converted_again() {
+    let dir = tempfile::tempdir().unwrap();
+    tar_gz_fixture(&dir, "Spark.tar.gz");
+    for _ in 0..2 {
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_datasets"))
+            .arg("--input-dir")
+            .arg(dir.path())
+            .arg("--output-dir")
+            .arg(dir.path())
+            .args(["--compress", "--force", "--quiet"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+    assert!(dir.path().join("Spark_logs.txt.gz").exists());
+    assert!(!dir.path().join("Spark_logs.txt_logs.txt.gz").exists());
+}
+
+#[test]
+fn split_outputs_roll_over_into_numbered_parts() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = tar_gz_fixture(&dir, "Spark.tar.gz");
+    let run = |out: &str, flags: &[&str]| {
+        let out = dir.path().join(out);
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_datasets"))
+            .arg(&path)
+            .arg("--output-dir")
+            .arg(&out)
+            .args(flags)
+            .arg("--quiet")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let mut parts: Vec<(String, String)> = std::fs::read_dir(&out)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|part| part.extension().is_some_and(|ext| ext == "txt"))
+            .map(|part| {
+                let name = part.file_name().unwrap().to_string_lossy().into_owned();
+                (name, std::fs::read_to_string(&part).unwrap())
+            })
+            .collect();
+        parts.sort();
+        parts
+    };
+    let part = |name: &str, lines: &str| (name.to_string(), lines.to_string());
+
+    assert_eq!(
+        run("lines", &["--split-lines", "3"]),
+        [
+            part("Spark_logs.0001.txt", "a1\na2\nb1\n"),
+            part("Spark_logs.0002.txt", "b3\n"),
+        ]
+    );
+    // A part is full once it holds the 4 bytes, so it ends with the line that crossed them.
+    assert_eq!(
+        run("bytes", &["--split-bytes", "4"]),
+        [
+            part("Spark_logs.0001.txt", "a1\na2\n"),
+            part("Spark_logs.0002.txt", "b1\nb3\n"),
+        ]
+    );
+    // Without lines only the empty first part is written, and then removed.
+    assert_eq!(
+        run("empty", &["--split-lines", "3", "--include", "none/*"]),
+        []
+    );
+}
+
+#[test]
+fn split_entries_flattening_to_the_same_name_are_kept_apart() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Android.zip");
+    let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+    for (entry, data) in [("a/b.log", "first\n"), ("a_b.log", "second\n")] {
+        writer.start_file(entry, FileOptions::default()).unwrap();
+        writer.write_all(data.as_bytes()).unwrap();
+    }
+    writer.finish().unwrap();
+    let out = dir.path().join("out");
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_datasets"))
+        .arg(&path)
+        .arg("--output-dir")
+        .arg(&out)
+        .args(["--split-by-file", "--quiet"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let split = out.join("Android");
+    assert_eq!(std::fs::read(split.join("a_b.log")).unwrap(), b"first\n");
+    assert_eq!(std::fs::read(split.join("a_b-2.log")).unwrap(), b"second\n");
+}
+
+#[test]
+fn log_lines_iterate_over_the_archive() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = tar_gz_fixture(&dir, "Spark.tar.gz");
+    let mut lines = LogLines::open(&path).unwrap();
+    let read: Vec<(String, u64, String)> = lines
+        .by_ref()
+        .map(|line| {
+            let line = line.unwrap();
+            assert_eq!(&*line.dataset, "Spark");
+            (line.file.to_string(), line.number, line.content)
+        })
+        .collect();
+    assert_eq!(
+        read,
+        [
+            ("logs/a.log".to_string(), 1, "a1".to_string()),
+            ("logs/a.log".to_string(), 2, "a2".to_string()),
+            ("logs/nested/b.log".to_string(), 1, "b1".to_string()),
+            ("logs/nested/b.log".to_string(), 3, "b3".to_string()),
+        ]
+    );
+    assert_eq!(lines.stats().unwrap().skipped_non_utf8, 1);
+
+    let converter = Converter::builder().head(1).build();
+    assert_eq!(converter.lines(&path).unwrap().count(), 1);
+    assert!(LogLines::open(&dir.path().join("missing.tar.gz")).is_err());
+}
+
+#[test]
+fn invalid_utf8_lines_can_be_kept_or_replaced() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = tar_gz_fixture(&dir, "Spark.tar.gz");
+    for (invalid_utf8, expected) in [
+        (
+            InvalidUtf8::Lossy,
+            "a1\na2\nb1\n\u{fffd}\u{fffd} broken\nb3\n",
+        ),
+        (InvalidUtf8::Replace, "a1\na2\nb1\n<invalid UTF-8>\nb3\n"),
+    ] {
+        let options = Options {
+            invalid_utf8,
+            ..Options::default()
+        };
+        let mut out = Vec::new();
+        let stats = stream_tar_gz(&path, &mut out, &options).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+        assert_eq!((stats.skipped_non_utf8, stats.replaced_non_utf8), (0, 1));
+    }
+}
+
+#[test]
+fn dedup_global_gives_the_same_lines_once_spilled_to_disk() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("access.log.gz");
+    let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+    for i in 0..1000 {
+        writeln!(encoder, "line {}", i % 300).unwrap();
+    }
+    encoder.finish().unwrap();
+    let outputs: Vec<(Vec<u8>, u64)> = [None, Some(1024)]
+        .into_iter()
+        .map(|max_memory| {
+            let options = Options {
+                dedup: Dedup::Global,
+                max_memory,
+                ..Options::default()
+            };
+            let mut out = Vec::new();
+            let stats = convert_archive_with(&path, &mut out, &options).unwrap();
+            (out, stats.duplicates)
+        })
+        .collect();
+    assert_eq!(outputs[0].1, 700);
+    assert_eq!(outputs[0], outputs[1]);
+}
+
+#[test]
+fn sidecar_rows_match_output_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = zip_fixture(&dir, "Android.zip");
+    let (mut out, mut sidecar) = (Vec::new(), Vec::new());
+    convert_archive_with_sidecar(&path, &mut out, &mut sidecar, &Options::default()).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "a1\na2\n\nb1\nb3\n\n");
+    // `FileOptions` stamps entries with the earliest time zip can express.
+    let rows: String = ["logs/a.log\t1", "logs/a.log\t2", "logs/a.log\t"]
+        .into_iter()
+        .chain([
+            "logs/nested/b.log\t1",
+            "logs/nested/b.log\t3",
+            "logs/nested/b.log\t",
+        ])
+        .map(|row| format!("{row}\t1980-01-01T00:00:00\n"))
+        .collect();
+    assert_eq!(String::from_utf8(sidecar).unwrap(), rows);
+}
+
+#[test]
+fn tee_writes_both_formats_in_one_pass() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = tar_gz_fixture(&dir, "Spark.tar.gz");
+    let (mut text, mut ndjson) = (Vec::new(), Vec::new());
+    let stats = convert_archive_tee(
+        &path,
+        &mut text,
+        &mut ndjson,
+        Format::Ndjson,
+        &Options::default(),
+    )
+    .unwrap();
+    assert_eq!(String::from_utf8(text).unwrap(), "a1\na2\nb1\nb3\n");
+    assert_eq!(stats.bytes, 12);
+    let lines: Vec<String> = String::from_utf8(ndjson)
+        .unwrap()
+        .lines()
+        .map(|record| {
+            let record: serde_json::Value = serde_json::from_str(record).unwrap();
+            record["line"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(lines, ["a1", "a2", "b1", "b3"]);
+}
+
+#[test]
+fn sqlite_rollback_drops_only_the_failed_archive() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("logs.db");
+    let mut sink = SqliteSink::create(&db).unwrap();
+    let path = zip_fixture(&dir, "Android.zip");
+    convert_archive_rows(&path, &mut sink, &Options::default()).unwrap();
+    sink.commit().unwrap();
+    // As if the next archive failed after its first lines.
+    sink.write_row("Broken", "x.log", 1, "x1").unwrap();
+    sink.rollback().unwrap();
+    sink.finish().unwrap();
+
+    let conn = rusqlite::Connection::open(&db).unwrap();
+    let datasets: Vec<String> = conn
+        .prepare("SELECT DISTINCT dataset FROM logs")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(datasets, ["Android"]);
+}
+
+#[test]
+fn sqlite_rows_keep_dataset_file_and_line_number() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("logs.db");
+    let mut sink = SqliteSink::create(&db).unwrap();
+    let path = zip_fixture(&dir, "Android.zip");
+    let stats = convert_archive_rows(&path, &mut sink, &Options::default()).unwrap();
+    sink.finish().unwrap();
+    assert_eq!(stats.lines, 4);
+
+    let conn = rusqlite::Connection::open(&db).unwrap();
+    let mut select = conn
+        .prepare("SELECT dataset, file, line_no, content FROM logs ORDER BY rowid")
+        .unwrap();
+    let rows: Vec<(String, String, i64, String)> = select
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    let row = |file: &str, n, line: &str| ("Android".into(), file.into(), n, line.into());
+    assert_eq!(
+        rows,
+        [
+            row("logs/a.log", 1, "a1"),
+            row("logs/a.log", 2, "a2"),
+            row("logs/nested/b.log", 1, "b1"),
+            row("logs/nested/b.log", 3, "b3"),
+        ]
+    );
+}
+
+#[test]
+fn csv_fields_are_quoted() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("access.log.gz");
+    let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+    encoder.write_all(b"plain\nsay \"hi\", twice\n").unwrap();
+    encoder.finish().unwrap();
+    let options = Options {
+        format: Format::Csv,
+        csv_header: true,
+        ..Options::default()
+    };
+    let mut out = Vec::new();
+    convert_archive_with(&path, &mut out, &options).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "dataset,file,mtime,line\n\
+         access.log,access.log,,plain\n\
+         access.log,access.log,,\"say \"\"hi\"\", twice\"\n"
+    );
+}
+
+#[test]
+fn ansi_escapes_are_stripped_from_colored_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = "\x1b[32mINFO\x1b[0m started\n\x1b[1;31mERROR\x1b[0m failed\x1b[K\nplain\n";
+    let path = dir.path().join("console.log.zst");
+    std::fs::write(&path, zstd::encode_all(data.as_bytes(), 0).unwrap()).unwrap();
+    let options = Options {
+        strip_ansi: true,
+        // Escape sequences straddle the boundaries of the read buffer.
+        buffer_size: Some(4),
+        ..Options::default()
+    };
+    let mut out = Vec::new();
+    convert_archive_with(&path, &mut out, &options).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "INFO started\nERROR failed\nplain\n"
+    );
+}
+
+#[test]
+fn json_lines_are_compacted_for_dedup() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = concat!(
+        "{\"msg\": \"a  b\", \"n\": [1, 2.50]}\n",
+        " { \"msg\":\"a  b\",\"n\":[1,2.50] }\n",
+        "{\"esc\": \"q\\\" x\"}\n",
+        "{ not json }\n",
+    );
+    let path = dir.path().join("events.jsonl.zst");
+    std::fs::write(&path, zstd::encode_all(data.as_bytes(), 0).unwrap()).unwrap();
+    let options = Options {
+        flatten_json_logs: true,
+        dedup: Dedup::Consecutive,
+        ..Options::default()
+    };
+    let mut out = Vec::new();
+    let stats = convert_archive_with(&path, &mut out, &options).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "{\"msg\":\"a  b\",\"n\":[1,2.50]}\n{\"esc\":\"q\\\" x\"}\n{ not json }\n"
+    );
+    assert_eq!(stats.duplicates, 1);
+}
+
+#[test]
+fn continuation_lines_are_joined_into_records() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = "\tat orphan\n2015 ERROR boom\n\tat A.b\n\tat C.d\n2015 INFO ok\n";
+    let path = dir.path().join("Hadoop_1.zst");
+    std::fs::write(&path, zstd::encode_all(data.as_bytes(), 0).unwrap()).unwrap();
+    let options = Options {
+        join_continuations: Some(Regex::new(r"^\d{4} ").unwrap()),
+        grep: RegexSet::new(["C\\.d", "orphan"]).unwrap(),
+        ..Options::default()
+    };
+    let mut out = Vec::new();
+    let stats = convert_archive_with(&path, &mut out, &options).unwrap();
+    // A continuation without a record to join stays on its own; records are filtered whole.
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "\tat orphan\n2015 ERROR boom\\n\tat A.b\\n\tat C.d\n"
+    );
+    assert_eq!(stats.grep_filtered, 1);
+}
+
+#[test]
+fn leading_timestamps_are_normalized_to_iso_8601() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = "2015-10-18 18:01:47,978 INFO started\n\tat Foo.bar\n2015-10-18 18:01:48 done\n";
+    let path = dir.path().join("Hadoop_1.zst");
+    std::fs::write(&path, zstd::encode_all(data.as_bytes(), 0).unwrap()).unwrap();
+    let mut options = Options {
+        normalize_timestamps: true,
+        ..Options::default()
+    };
+    let mut out = Vec::new();
+    convert_archive_with(&path, &mut out, &options).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "2015-10-18T18:01:47.978 INFO started\n\tat Foo.bar\n2015-10-18T18:01:48 done\n"
+    );
+
+    options.strict_time = true;
+    let mut out = Vec::new();
+    let stats = convert_archive_with(&path, &mut out, &options).unwrap();
+    assert_eq!(stats.lines, 2);
+    assert_eq!(stats.out_of_time, 1);
+}
+
+#[test]
+fn single_compressed_files_are_streamed_without_tar() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = b"l1\nl2\r\n";
+    let path = dir.path().join("access.log.zst");
+    std::fs::write(&path, zstd::encode_all(&data[..], 0).unwrap()).unwrap();
+    assert_eq!(dataset_stem(&path), "access.log");
+    let mut out = Vec::new();
+    let stats = convert_archive_with(&path, &mut out, &Options::default()).unwrap();
+    assert_eq!(out, b"l1\nl2\n");
+    assert_eq!(stats.files, 1);
+}
+
+#[test]
+fn tarball_with_single_file_suffix_is_unpacked() {
+    let dir = tempfile::tempdir().unwrap();
+    let tarball = tar_gz_fixture(&dir, "Spark.tar.gz");
+    let path = dir.path().join("Spark.gz");
+    std::fs::rename(tarball, &path).unwrap();
+    let mut out = Vec::new();
+    let stats = convert_archive_with(&path, &mut out, &Options::default()).unwrap();
+    assert_eq!(out, b"a1\na2\nb1\nb3\n");
+    assert_eq!(stats.files, 2);
+}
+
+#[test]
+fn gzipped_zip_is_unpacked_like_the_zip() {
+    let dir = tempfile::tempdir().unwrap();
+    let zip = std::fs::read(zip_fixture(&dir, "Android.zip")).unwrap();
+    let (expected, _) = convert(stream_zip, &dir.path().join("Android.zip"));
+    // By name and, with a single-file suffix, by the zip magic once decompressed.
+    for name in ["Android.zip.gz", "Android.gz"] {
+        let path = dir.path().join(name);
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(&zip).unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(
+            detect_archive_kind(&path).unwrap(),
+            Some(ArchiveKind::ZipGz)
+        );
+        assert_eq!(dataset_stem(&path), "Android");
+        let (out, stats) = convert(convert_archive_with, &path);
+        assert_eq!(out, expected, "{name}");
+        assert_eq!(stats.files, 2);
+    }
+}
+
+#[test]
+fn list_archive_reports_file_sizes() {
+    let dir = tempfile::tempdir().unwrap();
+    let expected: Vec<(String, u64)> = ENTRIES
+        .iter()
+        .map(|&(name, data)| (name.to_string(), data.len() as u64))
+        .collect();
+    // Only zip entries are compressed one by one.
+    for (path, compressed) in [
+        (tar_gz_fixture(&dir, "Spark.tar.gz"), false),
+        (zip_fixture(&dir, "Android.zip"), true),
+        (sevenz_fixture(&dir, "Mac.7z"), false),
+    ] {
+        let entries = list_archive(&path, &Options::default()).unwrap();
+        let sizes: Vec<_> = entries.iter().map(|e| (e.name.clone(), e.size)).collect();
+        assert_eq!(sizes, expected);
+        assert!(
+            entries
+                .iter()
+                .all(|e| e.compressed_size.is_some() == compressed
+                    && e.ratio().is_some() == compressed),
+            "{}",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn gzipped_tar_entries_are_listed_with_both_sizes() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = b"line\n".repeat(100);
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    gz.write_all(&data).unwrap();
+    let gz = gz.finish().unwrap();
+    let path = dir.path().join("HDFS.tar.gz");
+    let encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    let mut header = tar::Header::new_gnu();
+    header.set_size(gz.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, "logs/a.log.gz", &gz[..])
+        .unwrap();
+    builder.into_inner().unwrap().finish().unwrap();
+
+    let entries = list_archive(&path, &Options::default()).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].size, data.len() as u64);
+    assert_eq!(entries[0].compressed_size, Some(gz.len() as u64));
+}
+
+#[test]
+fn dataset_stem_strips_archive_suffixes() {
+    for (name, stem) in [
+        ("Spark.tar.gz", "Spark"),
+        ("HDFS_1.tgz", "HDFS_1"),
+        ("BGL.tar.bz2", "BGL"),
+        ("Thunderbird.tar.xz", "Thunderbird"),
+        ("Android_v2.zip", "Android_v2"),
+        ("Mac.7Z", "Mac"),
+        ("dir/Linux.tar.gz", "Linux"),
+    ] {
+        assert_eq!(dataset_stem(Path::new(name)), stem, "{name}");
+    }
+}
+
+#[test]
+fn zip_names_without_utf8_flag_are_decoded_as_cp437() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Windows.zip");
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    // An ASCII name is stored without the UTF-8 flag; patch in CP437 `é` (0x82) afterwards.
+    writer
+        .start_file("caf_.log", FileOptions::default())
+        .unwrap();
+    writer.write_all(b"w1\n").unwrap();
+    let mut bytes = writer.finish().unwrap().into_inner();
+    for i in 0..bytes.len() - 8 {
+        if &bytes[i..i + 8] == b"caf_.log" {
+            bytes[i + 3] = 0x82;
+        }
+    }
+    std::fs::write(&path, bytes).unwrap();
+
+    let entries = list_archive(&path, &Options::default()).unwrap();
+    assert_eq!(entries[0].name, "café.log");
+    let mut out = Vec::new();
+    let options = Options {
+        annotate: true,
+        ..Options::default()
+    };
+    convert_archive_with(&path, &mut out, &options).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "Windows\tcafé.log\t1980-01-01T00:00:00\tw1\n\n"
+    );
+}